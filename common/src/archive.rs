@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use crate::bytecode::{crc32, BytecodeError};
+use crate::object::{Binding, Object, Reader};
+
+/// First four bytes of every `.calib` archive
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"CLIB";
+/// Version of the archive layout described on [`Archive`]
+pub const ARCHIVE_FORMAT_VERSION: u16 = 1;
+
+/// magic(4) format_version(2) member_count(4) index_count(4) checksum(4)
+const HEADER_SIZE: usize = 18;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// Name of the object file the member was made from
+    pub name: String,
+    pub object: Object,
+}
+
+/// A static library: object files bundled together, with an index of the symbols they export
+///
+/// Everything is little-endian:
+/// ```text
+/// header:  magic "CLIB" | format version u16 | member count u32 | index count u32 | checksum u32
+/// index:   index count * (member u32 | name length u16 | UTF-8 name)
+/// members: member count * (name length u16 | UTF-8 name | length u32 | object file)
+/// ```
+/// The checksum is the CRC-32 of everything after the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub members: Vec<Member>,
+    /// Exported symbol to the member exporting it
+    index: BTreeMap<String, u32>,
+}
+
+impl Archive {
+    /// Bundles the members and indexes their exports.
+    /// Fails with the name of a symbol two members both export
+    pub fn new(members: Vec<Member>) -> Result<Self, String> {
+        let mut index = BTreeMap::new();
+        for (i, member) in members.iter().enumerate() {
+            let exports = member
+                .object
+                .symbols
+                .iter()
+                .filter(|s| s.binding == Binding::Export);
+            for symbol in exports {
+                if index.insert(symbol.name.clone(), i as u32).is_some() {
+                    return Err(symbol.name.clone());
+                }
+            }
+        }
+
+        Ok(Self { members, index })
+    }
+
+    /// Index of the member exporting `name`
+    pub fn member_exporting(&self, name: &str) -> Option<usize> {
+        self.index.get(name).map(|i| *i as usize)
+    }
+
+    /// Fails if a symbol or member name doesn't fit its 16 bit length field
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BytecodeError> {
+        let name_len = |name: &str| {
+            u16::try_from(name.len()).map_err(|_| BytecodeError::NameTooLong(name.to_owned()))
+        };
+
+        let mut body = vec![];
+        for (name, member) in &self.index {
+            body.extend_from_slice(&member.to_le_bytes());
+            body.extend_from_slice(&name_len(name)?.to_le_bytes());
+            body.extend_from_slice(name.as_bytes());
+        }
+        for member in &self.members {
+            let object = member.object.to_bytes()?;
+            body.extend_from_slice(&name_len(&member.name)?.to_le_bytes());
+            body.extend_from_slice(member.name.as_bytes());
+            body.extend_from_slice(&(object.len() as u32).to_le_bytes());
+            body.extend_from_slice(&object);
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&ARCHIVE_MAGIC);
+        bytes.extend_from_slice(&ARCHIVE_FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.members.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.index.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(4)? != ARCHIVE_MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+
+        let format_version = reader.u16()?;
+        if format_version != ARCHIVE_FORMAT_VERSION {
+            return Err(BytecodeError::UnsupportedFormatVersion(format_version));
+        }
+        let member_count = reader.u32()? as usize;
+        let index_count = reader.u32()? as usize;
+        let checksum = reader.u32()?;
+
+        let actual = crc32(&bytes[HEADER_SIZE..]);
+        if actual != checksum {
+            return Err(BytecodeError::ChecksumMismatch {
+                expected: checksum,
+                found: actual,
+            });
+        }
+
+        let mut index = BTreeMap::new();
+        for i in 0..index_count {
+            let member = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| BytecodeError::InvalidSymbol(i))?;
+            if member as usize >= member_count {
+                return Err(BytecodeError::InvalidMember(member as usize));
+            }
+            index.insert(name, member);
+        }
+
+        let mut members = vec![];
+        for i in 0..member_count {
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| BytecodeError::InvalidMember(i))?;
+            let len = reader.u32()? as usize;
+            let object = Object::from_bytes(reader.take(len)?)
+                .map_err(|_| BytecodeError::InvalidMember(i))?;
+            members.push(Member { name, object });
+        }
+
+        if reader.position != bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+
+        Ok(Self { members, index })
+    }
+}
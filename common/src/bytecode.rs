@@ -0,0 +1,318 @@
+use core::fmt;
+
+/// First four bytes of every crazyVM bytecode file
+pub const MAGIC: [u8; 4] = *b"CRZY";
+/// Version of the container layout described below
+pub const FORMAT_VERSION: u16 = 1;
+/// Version of the instruction set the code section is encoded with
+pub const ISA_VERSION: u16 = 9;
+/// Oldest instruction set version that still executes the same way, version 6
+/// started sign extending the Load/Store offsets, version 7 gave Call a frame and
+/// dropped the Fn opcode, renumbering the ones after it, and version 8 widened the
+/// register fields to 4 bits
+pub const MIN_ISA_VERSION: u16 = 8;
+
+/// magic(4) format_version(2) isa_version(2) entry(4) section_count(4) checksum(4)
+const HEADER_SIZE: usize = 20;
+/// kind(4) address(4) offset(4) length(4)
+const SECTION_ENTRY_SIZE: usize = 16;
+
+/// Code - Instructions, loaded into the program ROM
+/// Data - Words copied into RAM at the section address before execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SectionKind {
+    Code,
+    Data,
+}
+
+impl TryFrom<u32> for SectionKind {
+    type Error = BytecodeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Code),
+            1 => Ok(Self::Data),
+            _ => Err(BytecodeError::InvalidSectionKind(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    /// Where the section gets loaded (ROM index for code, RAM index for data)
+    pub address: u32,
+    pub data: Vec<u32>,
+}
+
+/// A crazyVM program as stored on disk
+///
+/// Everything is little-endian:
+/// ```text
+/// header:  magic "CRZY" | format version u16 | ISA version u16 | entry u32
+///          | section count u32 | checksum u32
+/// table:   section count * (kind u32 | address u32 | byte offset u32 | length in words u32)
+/// payload: the words of every section
+/// ```
+/// The checksum is the CRC-32 of everything after the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub isa_version: u16,
+    pub entry: u32,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    Truncated,
+    BadMagic,
+    UnsupportedFormatVersion(u16),
+    UnsupportedIsaVersion(u16),
+    ChecksumMismatch { expected: u32, found: u32 },
+    InvalidSectionKind(u32),
+    InvalidSectionAddress(usize),
+    SectionOutOfBounds(usize),
+    TrailingBytes,
+    NoCodeSection,
+    InvalidHex(String),
+    InvalidSymbol(usize),
+    InvalidRelocation(usize),
+    InvalidMember(usize),
+    InvalidLineEntry(usize),
+    NameTooLong(String),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::Truncated => write!(f, "File is truncated"),
+            BytecodeError::BadMagic => write!(f, "Not a crazyVM bytecode file (bad magic number)"),
+            BytecodeError::UnsupportedFormatVersion(v) => {
+                write!(f, "Unsupported bytecode format version {}", v)
+            }
+            BytecodeError::UnsupportedIsaVersion(v) => write!(
+                f,
+                "File targets ISA version {}, this build supports versions {} to {}",
+                v, MIN_ISA_VERSION, ISA_VERSION
+            ),
+            BytecodeError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Checksum mismatch (expected {:08x}, found {:08x})",
+                expected, found
+            ),
+            BytecodeError::InvalidSectionKind(k) => write!(f, "Invalid section kind {}", k),
+            BytecodeError::InvalidSectionAddress(i) => write!(
+                f,
+                "Section {} is loaded outside of the address space, code has to start at 0",
+                i
+            ),
+            BytecodeError::SectionOutOfBounds(i) => {
+                write!(f, "Section {} points outside of the file", i)
+            }
+            BytecodeError::TrailingBytes => write!(f, "Unexpected bytes after the last section"),
+            BytecodeError::NoCodeSection => write!(f, "File has no code section"),
+            BytecodeError::InvalidHex(s) => write!(f, "Invalid hex value in program: {}", s),
+            BytecodeError::InvalidSymbol(i) => write!(f, "Symbol {} is malformed", i),
+            BytecodeError::InvalidRelocation(i) => write!(f, "Relocation {} is malformed", i),
+            BytecodeError::InvalidMember(i) => write!(f, "Archive member {} is malformed", i),
+            BytecodeError::InvalidLineEntry(i) => write!(f, "Line entry {} is malformed", i),
+            BytecodeError::NameTooLong(name) => write!(
+                f,
+                "Name {}... is longer than {} bytes",
+                name.chars().take(32).collect::<String>(),
+                u16::MAX
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl Executable {
+    /// Single code section starting at 0, entry at the first instruction
+    pub fn new(code: Vec<u32>) -> Self {
+        Self {
+            isa_version: ISA_VERSION,
+            entry: 0,
+            sections: vec![Section {
+                kind: SectionKind::Code,
+                address: 0,
+                data: code,
+            }],
+        }
+    }
+
+    pub fn code(&self) -> Option<&Section> {
+        self.sections.iter().find(|s| s.kind == SectionKind::Code)
+    }
+
+    pub fn data(&self) -> impl Iterator<Item = &Section> {
+        self.sections.iter().filter(|s| s.kind == SectionKind::Data)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = vec![];
+        let mut offset = HEADER_SIZE + SECTION_ENTRY_SIZE * self.sections.len();
+
+        for section in &self.sections {
+            body.extend_from_slice(&(section.kind as u32).to_le_bytes());
+            body.extend_from_slice(&section.address.to_le_bytes());
+            body.extend_from_slice(&(offset as u32).to_le_bytes());
+            body.extend_from_slice(&(section.data.len() as u32).to_le_bytes());
+            offset += section.data.len() * 4;
+        }
+        for section in &self.sections {
+            for word in &section.data {
+                body.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.isa_version.to_le_bytes());
+        bytes.extend_from_slice(&self.entry.to_le_bytes());
+        bytes.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(BytecodeError::Truncated);
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+
+        let format_version = read_u16(bytes, 4);
+        if format_version != FORMAT_VERSION {
+            return Err(BytecodeError::UnsupportedFormatVersion(format_version));
+        }
+        let isa_version = read_u16(bytes, 6);
+        if !(MIN_ISA_VERSION..=ISA_VERSION).contains(&isa_version) {
+            return Err(BytecodeError::UnsupportedIsaVersion(isa_version));
+        }
+        let entry = read_u32(bytes, 8);
+        let section_count = read_u32(bytes, 12) as usize;
+        let checksum = read_u32(bytes, 16);
+
+        let actual = crc32(&bytes[HEADER_SIZE..]);
+        if actual != checksum {
+            return Err(BytecodeError::ChecksumMismatch {
+                expected: checksum,
+                found: actual,
+            });
+        }
+
+        let table_end = section_count
+            .checked_mul(SECTION_ENTRY_SIZE)
+            .and_then(|len| len.checked_add(HEADER_SIZE))
+            .ok_or(BytecodeError::Truncated)?;
+        if bytes.len() < table_end {
+            return Err(BytecodeError::Truncated);
+        }
+
+        let mut sections = Vec::with_capacity(section_count);
+        let mut end_of_data = table_end;
+
+        for i in 0..section_count {
+            let entry_start = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+            let kind = SectionKind::try_from(read_u32(bytes, entry_start))?;
+            let address = read_u32(bytes, entry_start + 4);
+            let offset = read_u32(bytes, entry_start + 8) as usize;
+            let length = read_u32(bytes, entry_start + 12) as usize;
+
+            let end = length
+                .checked_mul(4)
+                .and_then(|len| len.checked_add(offset))
+                .filter(|end| offset >= table_end && *end <= bytes.len())
+                .ok_or(BytecodeError::SectionOutOfBounds(i))?;
+
+            let data = bytes[offset..end]
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .collect();
+            end_of_data = end_of_data.max(end);
+
+            // Code always starts at 0 and data has to end inside the 32 bit address space
+            let fits = match kind {
+                SectionKind::Code => address == 0,
+                SectionKind::Data => address as u64 + length as u64 <= u32::MAX as u64 + 1,
+            };
+            if !fits {
+                return Err(BytecodeError::InvalidSectionAddress(i));
+            }
+
+            sections.push(Section {
+                kind,
+                address,
+                data,
+            });
+        }
+
+        if end_of_data != bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+
+        let executable = Self {
+            isa_version,
+            entry,
+            sections,
+        };
+        executable.code().ok_or(BytecodeError::NoCodeSection)?;
+
+        Ok(executable)
+    }
+
+    /// The old text format: space separated words, each one as
+    /// 8 hex digits written in reverse order. Only holds the code section
+    pub fn to_legacy_hex(&self) -> String {
+        self.code()
+            .map(|code| code.data.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|num| format!("{:08x}", num)) // Format as hex, zero-padded to 8 characters
+            .map(|hex_str| hex_str.chars().rev().collect::<String>()) // Reverse the hex string
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn from_legacy_hex(content: &str) -> Result<Self, BytecodeError> {
+        let mut buf = vec![];
+
+        for word in content.split_whitespace() {
+            let reversed: String = word.chars().rev().collect();
+
+            match u32::from_str_radix(&reversed, 16) {
+                Ok(num) => buf.push(num),
+                Err(_) => return Err(BytecodeError::InvalidHex(word.to_owned())),
+            }
+        }
+
+        Ok(Self::new(buf))
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// CRC-32 (IEEE 802.3)
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
@@ -29,7 +29,10 @@ impl Ram {
     }
 
     pub fn write_many(&mut self, bytes: &[u32], mut index: usize) -> Result<(), OutOfBoundsError> {
-        if index + bytes.len() > self.data.len() {
+        if index
+            .checked_add(bytes.len())
+            .is_none_or(|end| end > self.data.len())
+        {
             return Err(OutOfBoundsError(index));
         }
 
@@ -49,19 +52,13 @@ impl Ram {
         Ok(self.data[index])
     }
 
-    pub fn read_many(&self, mut index: usize, mut n: usize) -> Result<Vec<u32>, OutOfBoundsError> {
-        if self.data.len() <= index {
-            return Err(OutOfBoundsError(index));
+    /// Reads `n` words from `index`, the whole range has to be inside the memory
+    pub fn read_many(&self, index: usize, n: usize) -> Result<Vec<u32>, OutOfBoundsError> {
+        let end = index.checked_add(n);
+        match end.filter(|end| index < self.data.len() && *end <= self.data.len()) {
+            Some(end) => Ok(self.data[index..end].to_vec()),
+            // The first address that can't be read
+            None => Err(OutOfBoundsError(index.max(self.data.len()))),
         }
-
-        let mut buf = Vec::with_capacity(n);
-
-        while n > 0 {
-            buf.push(self.read(index)?);
-            index += 1;
-            n -= 1;
-        }
-
-        Ok(buf)
     }
 }
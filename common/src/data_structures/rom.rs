@@ -11,6 +11,14 @@ impl Rom {
         Self { data }
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn read(&self, index: usize) -> Result<u32, OutOfBoundsError> {
         if self.data.len() <= index {
             return Err(OutOfBoundsError(index));
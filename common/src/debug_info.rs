@@ -0,0 +1,233 @@
+use core::fmt;
+
+use crate::bytecode::{crc32, BytecodeError};
+use crate::object::Reader;
+
+/// First four bytes of every debug info file
+pub const DEBUG_INFO_MAGIC: [u8; 4] = *b"CRDB";
+/// Version of the debug info layout described on [`DebugInfo`]
+pub const DEBUG_INFO_FORMAT_VERSION: u16 = 1;
+
+/// magic(4) format_version(2) file_count(4) line_count(4) symbol_count(4) checksum(4)
+const HEADER_SIZE: usize = 22;
+
+/// The debug info of `program`, kept next to it: `program.bin.dbg`
+pub fn sidecar_path(program: &str) -> String {
+    format!("{}.dbg", program)
+}
+
+/// Where the instructions starting at `address` were written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    pub address: u32,
+    /// Index into the file names
+    pub file: u32,
+    /// Line and column count from 1
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A code label, function names included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugSymbol {
+    pub name: String,
+    pub address: u32,
+}
+
+/// Maps code addresses back to the source, kept next to the program in a `.dbg` file
+///
+/// Everything is little-endian:
+/// ```text
+/// header:  magic "CRDB" | format version u16 | file count u32 | line count u32
+///          | symbol count u32 | checksum u32
+/// files:   file count * (name length u16 | UTF-8 name)
+/// lines:   line count * (address u32 | file u32 | line u32 | column u32)
+/// symbols: symbol count * (address u32 | name length u16 | UTF-8 name)
+/// ```
+/// Lines and symbols are sorted by address. The checksum is the CRC-32 of everything after the header
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<LineEntry>,
+    pub symbols: Vec<DebugSymbol>,
+}
+
+/// An address as `label+offset (file:line)`, see [`DebugInfo::locate`]
+pub struct Location<'a> {
+    pub address: u32,
+    pub symbol: Option<(&'a str, u32)>,
+    pub source: Option<(&'a str, u32)>,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbol {
+            Some((name, 0)) => write!(f, "{}", name)?,
+            Some((name, offset)) => write!(f, "{}+{}", name, offset)?,
+            None => write!(f, "{}", self.address)?,
+        }
+        if let Some((file, line)) = self.source {
+            write!(f, " ({}:{})", file, line)?;
+        }
+        Ok(())
+    }
+}
+
+impl DebugInfo {
+    /// Index of `name` in the file names, added if it isn't there yet
+    pub fn file_index(&mut self, name: &str) -> u32 {
+        match self.files.iter().position(|f| f == name) {
+            Some(i) => i as u32,
+            None => {
+                self.files.push(name.to_string());
+                self.files.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Adds the debug info of code placed at `base`, used when linking
+    pub fn append(&mut self, other: &DebugInfo, base: u32) {
+        for line in &other.lines {
+            let file = self.file_index(&other.files[line.file as usize]);
+            self.lines.push(LineEntry {
+                address: line.address + base,
+                file,
+                ..*line
+            });
+        }
+        for symbol in &other.symbols {
+            self.symbols.push(DebugSymbol {
+                name: symbol.name.clone(),
+                address: symbol.address + base,
+            });
+        }
+        self.sort();
+    }
+
+    pub fn sort(&mut self) {
+        self.lines.sort_by_key(|l| l.address);
+        self.symbols
+            .sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+    }
+
+    /// The closest label at or before `address`, with the distance to it
+    pub fn symbol(&self, address: u32) -> Option<(&str, u32)> {
+        let i = self.symbols.partition_point(|s| s.address <= address);
+        let symbol = &self.symbols[i.checked_sub(1)?];
+        Some((&symbol.name, address - symbol.address))
+    }
+
+    /// File and line of the instruction at `address`
+    pub fn source(&self, address: u32) -> Option<(&str, u32)> {
+        let i = self.lines.partition_point(|l| l.address <= address);
+        let line = &self.lines[i.checked_sub(1)?];
+        Some((&self.files[line.file as usize], line.line))
+    }
+
+    pub fn locate(&self, address: u32) -> Location<'_> {
+        Location {
+            address,
+            symbol: self.symbol(address),
+            source: self.source(address),
+        }
+    }
+
+    /// Fails if a file or symbol name doesn't fit its 16 bit length field
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BytecodeError> {
+        let name_len = |name: &str| {
+            u16::try_from(name.len()).map_err(|_| BytecodeError::NameTooLong(name.to_owned()))
+        };
+
+        let mut body = vec![];
+        for file in &self.files {
+            body.extend_from_slice(&name_len(file)?.to_le_bytes());
+            body.extend_from_slice(file.as_bytes());
+        }
+        for line in &self.lines {
+            for value in [line.address, line.file, line.line, line.column] {
+                body.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        for symbol in &self.symbols {
+            body.extend_from_slice(&symbol.address.to_le_bytes());
+            body.extend_from_slice(&name_len(&symbol.name)?.to_le_bytes());
+            body.extend_from_slice(symbol.name.as_bytes());
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&DEBUG_INFO_MAGIC);
+        bytes.extend_from_slice(&DEBUG_INFO_FORMAT_VERSION.to_le_bytes());
+        for count in [self.files.len(), self.lines.len(), self.symbols.len()] {
+            bytes.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(4)? != DEBUG_INFO_MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+
+        let format_version = reader.u16()?;
+        if format_version != DEBUG_INFO_FORMAT_VERSION {
+            return Err(BytecodeError::UnsupportedFormatVersion(format_version));
+        }
+        let file_count = reader.u32()? as usize;
+        let line_count = reader.u32()? as usize;
+        let symbol_count = reader.u32()? as usize;
+        let checksum = reader.u32()?;
+
+        let actual = crc32(&bytes[HEADER_SIZE..]);
+        if actual != checksum {
+            return Err(BytecodeError::ChecksumMismatch {
+                expected: checksum,
+                found: actual,
+            });
+        }
+
+        let mut files = vec![];
+        for _ in 0..file_count {
+            let len = reader.u16()? as usize;
+            files.push(String::from_utf8_lossy(reader.take(len)?).into_owned());
+        }
+
+        let mut lines = vec![];
+        for i in 0..line_count {
+            let address = reader.u32()?;
+            let file = reader.u32()?;
+            if file as usize >= files.len() {
+                return Err(BytecodeError::InvalidLineEntry(i));
+            }
+            lines.push(LineEntry {
+                address,
+                file,
+                line: reader.u32()?,
+                column: reader.u32()?,
+            });
+        }
+
+        let mut symbols = vec![];
+        for i in 0..symbol_count {
+            let address = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| BytecodeError::InvalidSymbol(i))?;
+            symbols.push(DebugSymbol { name, address });
+        }
+
+        if reader.position != bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+
+        let mut info = Self {
+            files,
+            lines,
+            symbols,
+        };
+        info.sort();
+        Ok(info)
+    }
+}
@@ -1,14 +1,39 @@
 use core::fmt;
 use std::cmp::Ordering;
+use std::num::IntErrorKind;
 
 use macros::OpcodeTraits;
 
 use crate::registers::Register;
 
 /// The literal available in the Imm instruction
+/// Depending on the instruction it is either zero or sign extended
 #[derive(Debug, Clone, Copy)]
 pub struct Bit13Literal(pub u16);
 
+impl Bit13Literal {
+    /// Checked constructor for zero extended uses (0..=8191)
+    pub fn from_unsigned(value: i64) -> Result<Self, InvalidLiteralError> {
+        match value {
+            0..=8191 => Ok(Self(value as u16)),
+            _ if value < 0 => Err(InvalidLiteralError::Negative),
+            _ => Err(InvalidLiteralError::TooBig),
+        }
+    }
+
+    /// Checked constructor for sign extended uses (-4096..=4095)
+    pub fn from_signed(value: i64) -> Result<Self, InvalidLiteralError> {
+        match value {
+            -4096..=4095 => Ok(Self(value as u16 & 0x1fff)),
+            _ => Err(InvalidLiteralError::TooBig),
+        }
+    }
+
+    pub fn sign_extend(self) -> i32 {
+        ((self.0 as i32) << 19) >> 19
+    }
+}
+
 impl From<Bit13Literal> for u32 {
     fn from(val: Bit13Literal) -> Self {
         val.0 as u32
@@ -16,28 +41,76 @@ impl From<Bit13Literal> for u32 {
 }
 
 #[derive(Debug)]
-pub enum Invalid13BitLitError {
+pub enum InvalidLiteralError {
     TooBig,
+    Negative,
     InvalidDigit,
 }
 
+/// Parses a number literal: decimal, `#`/`0x` hexadecimal or `$`/`0b` binary,
+/// optionally preceded by a `-`
+pub fn parse_literal(value: &str) -> Result<i64, InvalidLiteralError> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (num, base) = if let Some(num) = value.strip_prefix('#') {
+        (num, 16)
+    } else if let Some(num) = value.strip_prefix('$') {
+        (num, 2)
+    } else if let Some(num) = value.strip_prefix("0x") {
+        (num, 16)
+    } else if let Some(num) = value.strip_prefix("0b") {
+        (num, 2)
+    } else {
+        (value, 10)
+    };
+
+    if num.starts_with(['+', '-']) {
+        return Err(InvalidLiteralError::InvalidDigit);
+    }
+    let num = u32::from_str_radix(num, base).map_err(|e| match e.kind() {
+        IntErrorKind::PosOverflow => InvalidLiteralError::TooBig,
+        _ => InvalidLiteralError::InvalidDigit,
+    })? as i64;
+
+    Ok(if negative { -num } else { num })
+}
+
+/// Accepts both unsigned (up to 8191) and negative (down to -4096) literals
 impl TryFrom<&str> for Bit13Literal {
-    type Error = Invalid13BitLitError;
+    type Error = InvalidLiteralError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let (num, base) = match value.chars().nth(0).unwrap() {
-            '#' => (&value[1..], 16),
-            '$' => (&value[1..], 2),
-            _ => (value, 10),
-        };
-        let num = match u16::from_str_radix(num, base) {
-            Ok(num) => num,
-            Err(_) => return Err(Invalid13BitLitError::InvalidDigit),
-        };
+        let num = parse_literal(value)?;
 
-        match num.cmp(&8191) {
-            Ordering::Greater => Err(Invalid13BitLitError::TooBig),
-            _ => Ok(Self(num)),
+        match num.cmp(&0) {
+            Ordering::Less => Self::from_signed(num),
+            _ => Self::from_unsigned(num),
+        }
+    }
+}
+
+/// The literal available in the ImmLo and ImmHi instructions
+#[derive(Debug, Clone, Copy)]
+pub struct Bit16Literal(pub u16);
+
+impl From<Bit16Literal> for u32 {
+    fn from(val: Bit16Literal) -> Self {
+        val.0 as u32
+    }
+}
+
+impl TryFrom<&str> for Bit16Literal {
+    type Error = InvalidLiteralError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let num = parse_literal(value)?;
+
+        match num {
+            0..=0xffff => Ok(Self(num as u16)),
+            _ if num < 0 => Err(InvalidLiteralError::Negative),
+            _ => Err(InvalidLiteralError::TooBig),
         }
     }
 }
@@ -72,11 +145,61 @@ pub enum Opcode {
     Jle(Bit13Literal),
     Jz(Bit13Literal),
     Jnz(Bit13Literal),
+    /// Call pushes the return address and the frame pointer, Ret drops the
+    /// frame and restores both
     Ret,
     Call(Bit13Literal),
-    Fn,
 
     Syscall,
+
+    /// Memory operations, the address is the second register plus the signed offset
+    Load(Register, Register, Bit13Literal),
+    Store(Register, Register, Bit13Literal),
+
+    /// Bitwise operations, shift amounts are taken modulo 32
+    And(Register, Register, Register),
+    Or(Register, Register, Register),
+    Xor(Register, Register, Register),
+    Not(Register, Register),
+    Shl(Register, Register, Register),
+    Shr(Register, Register, Register),
+    Sar(Register, Register, Register),
+    StackAnd,
+    StackOr,
+    StackXor,
+    StackNot,
+    StackShl,
+    StackShr,
+    StackSar,
+
+    /// Wide immediates, ImmLo sets the whole register to the literal,
+    /// ImmHi replaces the upper 16 bits and keeps the lower ones
+    ImmLo(Register, Bit16Literal),
+    ImmHi(Register, Bit16Literal),
+
+    /// Jumps on the carry, overflow and sign flags
+    Jc(Bit13Literal),
+    Jnc(Bit13Literal),
+    Jo(Bit13Literal),
+    Jno(Bit13Literal),
+    Js(Bit13Literal),
+    Jns(Bit13Literal),
+
+    /// Signed operations, SImm sign extends its literal
+    IMul(Register, Register, Register),
+    IDiv(Register, Register, Register),
+    SCmp(Register, Register),
+    SImm(Register, Bit13Literal),
+
+    /// IEEE-754 single precision operations on the register bits, they don't touch the flags.
+    /// ItoF converts a signed integer, FtoI truncates towards zero and saturates
+    FAdd(Register, Register, Register),
+    FSub(Register, Register, Register),
+    FMul(Register, Register, Register),
+    FDiv(Register, Register, Register),
+    FCmp(Register, Register),
+    ItoF(Register, Register),
+    FtoI(Register, Register),
 }
 
 impl fmt::Display for Opcode {
@@ -103,48 +226,171 @@ impl fmt::Display for Opcode {
             Syscall => "Syscall",
             Ret => "Ret",
             Call(..) => "Call",
-            Fn => "Fn",
             StackAdd => "StackAdd",
             StackSub => "StackSub",
             StackMul => "StackMul",
             StackDiv => "StackDiv",
             PushImm(..) => "PushImm",
+            Load(..) => "Load",
+            Store(..) => "Store",
+            And(..) => "And",
+            Or(..) => "Or",
+            Xor(..) => "Xor",
+            Not(..) => "Not",
+            Shl(..) => "Shl",
+            Shr(..) => "Shr",
+            Sar(..) => "Sar",
+            StackAnd => "StackAnd",
+            StackOr => "StackOr",
+            StackXor => "StackXor",
+            StackNot => "StackNot",
+            StackShl => "StackShl",
+            StackShr => "StackShr",
+            StackSar => "StackSar",
+            ImmLo(..) => "ImmLo",
+            ImmHi(..) => "ImmHi",
+            Jc(..) => "Jc",
+            Jnc(..) => "Jnc",
+            Jo(..) => "Jo",
+            Jno(..) => "Jno",
+            Js(..) => "Js",
+            Jns(..) => "Jns",
+            IMul(..) => "IMul",
+            IDiv(..) => "IDiv",
+            SCmp(..) => "SCmp",
+            SImm(..) => "SImm",
+            FAdd(..) => "FAdd",
+            FSub(..) => "FSub",
+            FMul(..) => "FMul",
+            FDiv(..) => "FDiv",
+            FCmp(..) => "FCmp",
+            ItoF(..) => "ItoF",
+            FtoI(..) => "FtoI",
         };
 
         match *self {
-            Add(r1, r2, r3) | Sub(r1, r2, r3) | Mul(r1, r2, r3) | Div(r1, r2, r3) => {
+            Add(r1, r2, r3)
+            | Sub(r1, r2, r3)
+            | Mul(r1, r2, r3)
+            | Div(r1, r2, r3)
+            | And(r1, r2, r3)
+            | Or(r1, r2, r3)
+            | Xor(r1, r2, r3)
+            | Shl(r1, r2, r3)
+            | Shr(r1, r2, r3)
+            | Sar(r1, r2, r3)
+            | IMul(r1, r2, r3)
+            | IDiv(r1, r2, r3)
+            | FAdd(r1, r2, r3)
+            | FSub(r1, r2, r3)
+            | FMul(r1, r2, r3)
+            | FDiv(r1, r2, r3) => {
                 write!(f, "{} {} {} {}", op_name, r1, r2, r3)
             }
-            Cmp(r1, r2) => {
+            Cmp(r1, r2)
+            | Not(r1, r2)
+            | SCmp(r1, r2)
+            | FCmp(r1, r2)
+            | ItoF(r1, r2)
+            | FtoI(r1, r2) => {
                 write!(f, "{} {} {}", op_name, r1, r2)
             }
             Jmp(imm) | Je(imm) | Jne(imm) | Jg(imm) | Jge(imm) | Jz(imm) | Jnz(imm) | Jl(imm)
-            | Jle(imm) | Call(imm) | PushImm(imm) => {
+            | Jle(imm) | Call(imm) | PushImm(imm) | Jc(imm) | Jnc(imm) | Jo(imm) | Jno(imm)
+            | Js(imm) | Jns(imm) => {
                 write!(f, "{} {}", op_name, imm.0)
             }
             Imm(r1, lit) => write!(f, "{} {} {}", op_name, r1, lit.0),
+            ImmLo(r1, lit) | ImmHi(r1, lit) => write!(f, "{} {} {}", op_name, r1, lit.0),
+            Load(r1, r2, offset) | Store(r1, r2, offset) => match offset.sign_extend() {
+                0 => write!(f, "{} {} [{}]", op_name, r1, r2),
+                offset if offset < 0 => write!(f, "{} {} [{} - {}]", op_name, r1, r2, -offset),
+                offset => write!(f, "{} {} [{} + {}]", op_name, r1, r2, offset),
+            },
+            SImm(r1, lit) => write!(f, "{} {} {}", op_name, r1, lit.sign_extend()),
             Push(r1) | Pop(r1) => write!(f, "{} {}", op_name, r1),
 
-            Syscall | StackAdd | StackSub | StackMul | StackDiv | Ret | Fn => {
+            Syscall | StackAdd | StackSub | StackMul | StackDiv | Ret | StackAnd | StackOr
+            | StackXor | StackNot | StackShl | StackShr | StackSar => {
                 write!(f, "{}", op_name)
             }
         }
     }
 }
 
+/// The bit field of an instruction word that failed to decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeField {
+    Opcode,
+    R1,
+    R2,
+    R3,
+}
+
+impl DecodeField {
+    /// First bit and width of the field
+    pub fn bits(&self) -> (u32, u32) {
+        match self {
+            DecodeField::Opcode => (0, 8),
+            DecodeField::R1 => (8, 4),
+            DecodeField::R2 => (12, 4),
+            DecodeField::R3 => (16, 4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub word: u32,
+    pub field: DecodeField,
+}
+
+impl DecodeError {
+    /// Value of the faulty field
+    pub fn value(&self) -> u32 {
+        let (start, width) = self.field.bits();
+        (self.word >> start) & ((1 << width) - 1)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, width) = self.field.bits();
+        let what = match self.field {
+            DecodeField::Opcode => "opcode",
+            DecodeField::R1 | DecodeField::R2 | DecodeField::R3 => "register",
+        };
+        write!(
+            f,
+            "Invalid {} {} in bits {}..{} of instruction {:#010x}",
+            what,
+            self.value(),
+            start,
+            start + width,
+            self.word
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 /// Useful trait to be used on raw u32s to get encoded values
 trait Instruction {
     fn op(&self) -> u8;
-    fn r1(&self) -> Register;
-    fn r2(&self) -> Register;
-    fn r3(&self) -> Register;
+    fn r1(&self) -> Result<Register, DecodeError>;
+    fn r2(&self) -> Result<Register, DecodeError>;
+    fn r3(&self) -> Result<Register, DecodeError>;
     fn lit13(&self) -> Bit13Literal;
+    fn offset13(&self) -> Bit13Literal;
+    fn lit16(&self) -> Bit16Literal;
 
     fn reg_1_instruction(&mut self, r1: Register) -> u32;
     fn reg_2_instruction(&mut self, r1: Register, r2: Register) -> u32;
     fn reg_3_instruction(&mut self, r1: Register, r2: Register, r3: Register) -> u32;
     fn imm_instruction(&mut self, r1: Register, imm: Bit13Literal) -> u32;
     fn jump_instruction(&mut self, imm: Bit13Literal) -> u32;
+    fn mem_instruction(&mut self, r1: Register, r2: Register, offset: Bit13Literal) -> u32;
+    fn imm16_instruction(&mut self, r1: Register, imm: Bit16Literal) -> u32;
 }
 
 impl Instruction for u32 {
@@ -152,48 +398,78 @@ impl Instruction for u32 {
         (self & 0x000000ff) as u8
     }
 
-    fn r1(&self) -> Register {
-        ((self >> 8) & 0x07).into()
+    fn r1(&self) -> Result<Register, DecodeError> {
+        Register::try_from((self >> 8) & 0x0f).map_err(|_| DecodeError {
+            word: *self,
+            field: DecodeField::R1,
+        })
     }
 
-    fn r2(&self) -> Register {
-        ((self >> 11) & 0x07).into()
+    fn r2(&self) -> Result<Register, DecodeError> {
+        Register::try_from((self >> 12) & 0x0f).map_err(|_| DecodeError {
+            word: *self,
+            field: DecodeField::R2,
+        })
     }
 
-    fn r3(&self) -> Register {
-        ((self >> 14) & 0x07).into()
+    fn r3(&self) -> Result<Register, DecodeError> {
+        Register::try_from((self >> 16) & 0x0f).map_err(|_| DecodeError {
+            word: *self,
+            field: DecodeField::R3,
+        })
     }
 
     fn lit13(&self) -> Bit13Literal {
-        Bit13Literal(((self >> 11) & 0x1fff) as u16)
+        Bit13Literal(((self >> 12) & 0x1fff) as u16)
+    }
+
+    fn offset13(&self) -> Bit13Literal {
+        Bit13Literal(((self >> 16) & 0x1fff) as u16)
+    }
+
+    fn lit16(&self) -> Bit16Literal {
+        Bit16Literal(((self >> 12) & 0xffff) as u16)
     }
 
     fn reg_1_instruction(&mut self, r1: Register) -> u32 {
-        *self |= (u32::from(r1) & 0x07) << 8;
+        *self |= (u32::from(r1) & 0x0f) << 8;
         *self
     }
 
     fn reg_2_instruction(&mut self, r1: Register, r2: Register) -> u32 {
-        *self |= (u32::from(r1) & 0x07) << 8;
-        *self |= (u32::from(r2) & 0x07) << 11;
+        *self |= (u32::from(r1) & 0x0f) << 8;
+        *self |= (u32::from(r2) & 0x0f) << 12;
         *self
     }
 
     fn reg_3_instruction(&mut self, r1: Register, r2: Register, r3: Register) -> u32 {
-        *self |= (u32::from(r1) & 0x07) << 8;
-        *self |= (u32::from(r2) & 0x07) << 11;
-        *self |= (u32::from(r3) & 0x07) << 14;
+        *self |= (u32::from(r1) & 0x0f) << 8;
+        *self |= (u32::from(r2) & 0x0f) << 12;
+        *self |= (u32::from(r3) & 0x0f) << 16;
         *self
     }
 
     fn imm_instruction(&mut self, r1: Register, imm: Bit13Literal) -> u32 {
-        *self |= (u32::from(r1) & 0x07) << 8;
-        *self |= (u32::from(imm) & 0x1fff) << 11;
+        *self |= (u32::from(r1) & 0x0f) << 8;
+        *self |= (u32::from(imm) & 0x1fff) << 12;
         *self
     }
 
     fn jump_instruction(&mut self, imm: Bit13Literal) -> u32 {
-        *self |= (u32::from(imm) & 0x1fff) << 11;
+        *self |= (u32::from(imm) & 0x1fff) << 12;
+        *self
+    }
+
+    fn mem_instruction(&mut self, r1: Register, r2: Register, offset: Bit13Literal) -> u32 {
+        *self |= (u32::from(r1) & 0x0f) << 8;
+        *self |= (u32::from(r2) & 0x0f) << 12;
+        *self |= (u32::from(offset) & 0x1fff) << 16;
+        *self
+    }
+
+    fn imm16_instruction(&mut self, r1: Register, imm: Bit16Literal) -> u32 {
+        *self |= (u32::from(r1) & 0x0f) << 8;
+        *self |= (u32::from(imm) & 0xffff) << 12;
         *self
     }
 }
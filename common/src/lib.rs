@@ -1,4 +1,9 @@
+pub mod archive;
+pub mod bytecode;
 pub mod data_structures;
+pub mod debug_info;
 pub mod instructions;
 pub mod machine;
+pub mod object;
 pub mod registers;
+pub mod syscall;
@@ -1,33 +1,79 @@
-use crate::instructions::Opcode;
-use crate::registers::{Register, Registers};
+use crate::bytecode::Executable;
+use crate::instructions::{Bit13Literal, DecodeError, Opcode};
+use crate::registers::{flags, Register, Registers};
+use crate::syscall::{StdSyscalls, SyscallError, SyscallHandler};
 use core::fmt;
+use std::cmp::Ordering;
 
-use crate::data_structures::{ram::Ram, rom::Rom};
+use crate::data_structures::{error::OutOfBoundsError, ram::Ram, rom::Rom};
+
+/// Words of memory a VM gets unless told otherwise
+pub const DEFAULT_MEMORY_SIZE: usize = 1024 * 1024 * 4;
 
 /// The virtual machine state struct itself
 pub struct CrazyVM {
     program: Rom,
     registers: Registers,
     memory: Ram,
-    skipping_body: bool,
+    syscalls: Box<dyn SyscallHandler>,
+    /// Address of the instruction currently being executed
+    current_instruction: u32,
+    /// Calls that haven't returned yet, the innermost last
+    call_stack: Vec<Frame>,
+}
+
+/// A `Call` that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the `Call` instruction, the function returns right after it
+    pub call_site: u32,
+    /// Address of the called function
+    pub function: u32,
 }
 
 /// NoNextInstruction - Signals to the manager to stop stepping the VM
+/// Every other variant is a fault, holding the address of the faulting instruction
 #[derive(Debug)]
 pub enum RuntimeError {
-    StackOverflow,
-    StackUnderflow,
-    MemoryWrite,
+    StackOverflow(u32),
+    StackUnderflow(u32),
+    DivisionByZero(u32),
+    /// Only raised by `IDiv` of `i32::MIN` by -1. Add, Sub, Mul and their stack versions
+    /// wrap instead and report the overflow in the Carry and Overflow flags
+    ArithmeticOverflow(u32),
+    OutOfBounds(u32, OutOfBoundsError),
+    Syscall(u32, SyscallError),
+    InvalidInstruction(u32, DecodeError),
     NoNextInstruction,
 }
 
+impl RuntimeError {
+    /// Address of the instruction that caused the fault
+    pub fn pc(&self) -> Option<u32> {
+        match *self {
+            RuntimeError::StackOverflow(pc)
+            | RuntimeError::StackUnderflow(pc)
+            | RuntimeError::DivisionByZero(pc)
+            | RuntimeError::ArithmeticOverflow(pc)
+            | RuntimeError::OutOfBounds(pc, _)
+            | RuntimeError::Syscall(pc, _)
+            | RuntimeError::InvalidInstruction(pc, _) => Some(pc),
+            RuntimeError::NoNextInstruction => None,
+        }
+    }
+}
+
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let msg = match *self {
-            RuntimeError::MemoryWrite => "Failed to write to memory!",
-            RuntimeError::StackOverflow => "Stack overflew!",
-            RuntimeError::StackUnderflow => "Stack underflew!",
+        let msg = match self {
+            RuntimeError::StackOverflow(_) => "Stack overflew!",
+            RuntimeError::StackUnderflow(_) => "Stack underflew!",
+            RuntimeError::DivisionByZero(_) => "Division by zero!",
+            RuntimeError::ArithmeticOverflow(_) => "Arithmetic overflow!",
             RuntimeError::NoNextInstruction => "Failed to get next instruction!",
+            RuntimeError::OutOfBounds(_, e) => return write!(f, "{}!", e),
+            RuntimeError::Syscall(_, e) => return write!(f, "{}!", e),
+            RuntimeError::InvalidInstruction(_, e) => return write!(f, "{}!", e),
         };
 
         write!(f, "{}", msg)
@@ -40,91 +86,142 @@ impl CrazyVM {
             program: program.into(),
             registers: Default::default(),
             memory: Ram::new(mem_size),
-            skipping_body: false,
+            syscalls: Box::new(StdSyscalls::default()),
+            current_instruction: 0,
+            call_stack: vec![],
         }
     }
 
-    fn get_next_instruction(&mut self) -> Option<Opcode> {
-        match self.program.read(self.registers[Register::PC] as usize) {
-            Ok(p) => {
-                self.registers[Register::PC] += 1;
-                Some(p.into())
-            }
-            Err(_) => None,
+    /// Loads the code section, copies the data sections into memory and starts
+    /// execution at the entry point. The stack starts right after the data
+    pub fn from_executable(
+        executable: &Executable,
+        mem_size: usize,
+    ) -> Result<Self, OutOfBoundsError> {
+        let program = executable.code().map_or(&[][..], |code| &code.data);
+        let mut machine = Self::new(program, mem_size);
+        machine.registers[Register::PC] = executable.entry;
+
+        for section in executable.data() {
+            machine
+                .memory
+                .write_many(&section.data, section.address as usize)?;
+            let end = section.address.saturating_add(section.data.len() as u32);
+            machine.registers[Register::SP] = machine.registers[Register::SP].max(end);
         }
+
+        Ok(machine)
     }
 
-    fn stack_push(&mut self, r: Register) -> Result<(), RuntimeError> {
-        if (self.registers[Register::SP] + 1) as usize >= self.memory.max_size() {
-            return Err(RuntimeError::StackOverflow);
-        }
+    /// Replaces the standard stdin/stdout syscalls
+    pub fn set_syscall_handler(&mut self, handler: Box<dyn SyscallHandler>) {
+        self.syscalls = handler;
+    }
 
-        self.memory
-            .write(self.registers[r], self.registers[Register::SP] as usize)
-            .ok()
-            .ok_or(RuntimeError::MemoryWrite)?;
-        self.registers[Register::SP] += 1;
-        Ok(())
+    fn get_next_instruction(&mut self) -> Result<Opcode, RuntimeError> {
+        let pc = self.registers[Register::PC];
+        let word = self
+            .program
+            .read(pc as usize)
+            .map_err(|_| RuntimeError::NoNextInstruction)?;
+        let ins = Opcode::try_from(word).map_err(|e| RuntimeError::InvalidInstruction(pc, e))?;
+
+        self.registers[Register::PC] += 1;
+        Ok(ins)
     }
 
-    fn stack_pop(&mut self, r: Register) -> Result<(), RuntimeError> {
-        if self.registers[Register::SP] < 1 {
-            return Err(RuntimeError::StackUnderflow);
-        }
-        self.registers[Register::SP] -= 1;
-        self.registers[r] = self
-            .memory
-            .read(self.registers[Register::SP] as usize)
-            .ok()
-            .ok_or(RuntimeError::MemoryWrite)?;
+    fn stack_push(&mut self, r: Register) -> Result<(), RuntimeError> {
+        self.stack_push_internal(self.registers[r])
+    }
 
+    fn stack_pop(&mut self, r: Register) -> Result<(), RuntimeError> {
+        self.registers[r] = self.stack_pop_internal()?;
         Ok(())
     }
 
-    fn stack_pop_internal(&mut self) -> u32 {
+    fn stack_pop_internal(&mut self) -> Result<u32, RuntimeError> {
+        let pc = self.current_instruction;
+        if self.registers[Register::SP] < 1 {
+            return Err(RuntimeError::StackUnderflow(pc));
+        }
         self.registers[Register::SP] -= 1;
         self.memory
             .read(self.registers[Register::SP] as usize)
-            .unwrap()
+            .map_err(|e| RuntimeError::OutOfBounds(pc, e))
     }
 
     fn stack_push_internal(&mut self, val: u32) -> Result<(), RuntimeError> {
-        if (self.registers[Register::SP] + 1) as usize >= self.memory.max_size() {
-            return Err(RuntimeError::StackOverflow);
+        let pc = self.current_instruction;
+        if self.registers[Register::SP] as usize + 1 >= self.memory.max_size() {
+            return Err(RuntimeError::StackOverflow(pc));
         }
 
         self.memory
             .write(val, self.registers[Register::SP] as usize)
-            .ok()
-            .ok_or(RuntimeError::MemoryWrite)?;
+            .map_err(|e| RuntimeError::OutOfBounds(pc, e))?;
         self.registers[Register::SP] += 1;
         Ok(())
     }
 
-    pub fn step(&mut self) -> Result<Option<u32>, RuntimeError> {
-        let ins = self
-            .get_next_instruction()
-            .ok_or(RuntimeError::NoNextInstruction)?;
+    /// Whether any of the given Flag bits is set
+    fn flag(&self, mask: u32) -> bool {
+        self.registers[Register::Flag] & mask != 0
+    }
 
-        if self.skipping_body {
-            if let Opcode::Ret = ins {
-                self.skipping_body = false;
-                return Ok(None);
-            }
-            return Ok(None);
+    fn float(&self, r: Register) -> f32 {
+        f32::from_bits(self.registers[r])
+    }
+
+    fn jump_if(&mut self, condition: bool, imm: Bit13Literal) {
+        if condition {
+            self.registers[Register::PC] = imm.into();
         }
+    }
+
+    /// Stores the Zero, Carry, Overflow and Sign flags of an arithmetic result,
+    /// the comparison flags are left untouched
+    fn with_flags(&mut self, (result, carry, overflow): (u32, bool, bool)) -> u32 {
+        let mut flag = self.registers[Register::Flag] & !flags::ARITHMETIC;
+        flag |= if result == 0 { flags::ZERO } else { 0 };
+        flag |= if carry { flags::CARRY } else { 0 };
+        flag |= if overflow { flags::OVERFLOW } else { 0 };
+        flag |= if result >> 31 == 1 { flags::SIGN } else { 0 };
+        self.registers[Register::Flag] = flag;
+        result
+    }
+
+    /// Replaces the Flag register with the result of a comparison, `None` meaning
+    /// unordered (a NaN operand). `arithmetic` holds the Zero, Carry, Overflow and Sign bits
+    fn set_comparison_flags(&mut self, ordering: Option<Ordering>, arithmetic: u32) {
+        let mut flag = arithmetic & flags::ARITHMETIC;
+        flag |= match ordering {
+            Some(Ordering::Less) => flags::LESS | flags::NOT_EQUAL,
+            Some(Ordering::Greater) => flags::GREATER | flags::NOT_EQUAL,
+            Some(Ordering::Equal) => flags::EQUAL,
+            None => flags::NOT_EQUAL,
+        };
+        self.registers[Register::Flag] = flag;
+    }
+
+    pub fn step(&mut self) -> Result<Option<u32>, RuntimeError> {
+        let pc = self.registers[Register::PC];
+        self.current_instruction = pc;
+        let ins = self.get_next_instruction()?;
         match ins {
             Opcode::Add(r1, r2, r3) => {
-                self.registers[r3] = self.registers[r1] + self.registers[r2];
+                self.registers[r3] = self.with_flags(add(self.registers[r1], self.registers[r2]));
             }
             Opcode::Sub(r1, r2, r3) => {
-                self.registers[r3] = self.registers[r1] - self.registers[r2];
+                self.registers[r3] = self.with_flags(sub(self.registers[r1], self.registers[r2]));
             }
             Opcode::Mul(r1, r2, r3) => {
-                self.registers[r3] = self.registers[r1] * self.registers[r2];
+                self.registers[r3] = self.with_flags(mul(self.registers[r1], self.registers[r2]));
             }
             Opcode::Div(r1, r2, r3) => {
-                self.registers[r3] = self.registers[r1] / self.registers[r2];
+                let result = self.registers[r1]
+                    .checked_div(self.registers[r2])
+                    .ok_or(RuntimeError::DivisionByZero(pc))?;
+                self.registers[r3] = self.with_flags((result, false, false));
             }
             Opcode::Imm(r1, imm) => {
                 self.registers[r1] = imm.into();
@@ -132,151 +229,272 @@ impl CrazyVM {
             Opcode::Push(r1) => self.stack_push(r1)?,
             Opcode::PushImm(imm) => self.stack_push_internal(imm.0.into())?,
             Opcode::Pop(r1) => self.stack_pop(r1)?,
-            // Zero, Less, More, Eq, NotEq
             Opcode::Cmp(r1, r2) => {
-                self.registers[Register::Flag] = 0;
-                self.registers[Register::Flag] |= (self.registers[r1] == 0) as u32;
-                self.registers[Register::Flag] |=
-                    ((self.registers[r1] < self.registers[r2]) as u32) << 1;
-                self.registers[Register::Flag] |=
-                    ((self.registers[r1] > self.registers[r2]) as u32) << 2;
-                self.registers[Register::Flag] |=
-                    ((self.registers[r1] == self.registers[r2]) as u32) << 3;
-                self.registers[Register::Flag] |=
-                    ((self.registers[r1] != self.registers[r2]) as u32) << 4;
+                let (a, b) = (self.registers[r1], self.registers[r2]);
+                self.set_comparison_flags(Some(a.cmp(&b)), compare_flags(a, b));
             }
             Opcode::Jmp(imm) => {
                 self.registers[Register::PC] = imm.into();
             }
-            Opcode::Je(imm) => {
-                if self.registers[Register::Flag] & (1 << 3) == (1 << 3) {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::Je(imm) => self.jump_if(self.flag(flags::EQUAL), imm),
+            Opcode::Jne(imm) => self.jump_if(self.flag(flags::NOT_EQUAL), imm),
+            Opcode::Jg(imm) => self.jump_if(self.flag(flags::GREATER), imm),
+            Opcode::Jge(imm) => self.jump_if(self.flag(flags::GREATER | flags::EQUAL), imm),
+            Opcode::Jz(imm) => self.jump_if(self.flag(flags::ZERO), imm),
+            Opcode::Jnz(imm) => self.jump_if(!self.flag(flags::ZERO), imm),
+            Opcode::Jl(imm) => self.jump_if(self.flag(flags::LESS), imm),
+            Opcode::Jle(imm) => self.jump_if(self.flag(flags::LESS | flags::EQUAL), imm),
+            Opcode::Jc(imm) => self.jump_if(self.flag(flags::CARRY), imm),
+            Opcode::Jnc(imm) => self.jump_if(!self.flag(flags::CARRY), imm),
+            Opcode::Jo(imm) => self.jump_if(self.flag(flags::OVERFLOW), imm),
+            Opcode::Jno(imm) => self.jump_if(!self.flag(flags::OVERFLOW), imm),
+            Opcode::Js(imm) => self.jump_if(self.flag(flags::SIGN), imm),
+            Opcode::Jns(imm) => self.jump_if(!self.flag(flags::SIGN), imm),
+            Opcode::Ret => {
+                // Anything the function left on the stack is dropped with its frame
+                self.registers[Register::SP] = self.registers[Register::FP];
+                self.stack_pop(Register::FP)?;
+                self.stack_pop(Register::PC)?;
+                self.call_stack.pop();
             }
-            Opcode::Jne(imm) => {
-                if self.registers[Register::Flag] & (1 << 4) == (1 << 4) {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::Call(imm) => {
+                self.stack_push(Register::PC)?;
+                self.stack_push(Register::FP)?;
+                self.registers[Register::FP] = self.registers[Register::SP];
+                self.registers[Register::PC] = imm.into();
+                self.call_stack.push(Frame {
+                    call_site: pc,
+                    function: imm.into(),
+                });
             }
-            Opcode::Jg(imm) => {
-                if self.registers[Register::Flag] & (1 << 2) == (1 << 2) {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::StackAdd => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                let result = self.with_flags(add(b, a));
+                self.stack_push_internal(result)?;
             }
-            Opcode::Jge(imm) => {
-                if self.registers[Register::Flag] & (1 << 2 | 1 << 3) == (1 << 2 | 1 << 3) {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::StackSub => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                let result = self.with_flags(sub(b, a));
+                self.stack_push_internal(result)?;
             }
-            Opcode::Jz(imm) => {
-                if self.registers[Register::Flag] & 1 == 1 {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::StackMul => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                let result = self.with_flags(mul(b, a));
+                self.stack_push_internal(result)?;
             }
-            Opcode::Jnz(imm) => {
-                if self.registers[Register::Flag] & 1 == 0 {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::StackDiv => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                let result = b.checked_div(a).ok_or(RuntimeError::DivisionByZero(pc))?;
+                let result = self.with_flags((result, false, false));
+                self.stack_push_internal(result)?;
             }
-            Opcode::Jl(imm) => {
-                if self.registers[Register::Flag] & (1 << 1) == (1 << 1) {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::Load(r1, r2, offset) => {
+                let addr = self.registers[r2].wrapping_add(offset.sign_extend() as u32);
+                self.registers[r1] = self
+                    .memory
+                    .read(addr as usize)
+                    .map_err(|e| RuntimeError::OutOfBounds(pc, e))?;
             }
-            Opcode::Jle(imm) => {
-                if self.registers[Register::Flag] & (1 << 1 | 1 << 3) == (1 << 1 | 1 << 3) {
-                    self.registers[Register::PC] = imm.into()
-                }
+            Opcode::Store(r1, r2, offset) => {
+                let addr = self.registers[r2].wrapping_add(offset.sign_extend() as u32);
+                self.memory
+                    .write(self.registers[r1], addr as usize)
+                    .map_err(|e| RuntimeError::OutOfBounds(pc, e))?;
             }
-            Opcode::Ret => {
-                self.stack_pop(Register::PC).unwrap();
+            Opcode::And(r1, r2, r3) => {
+                self.registers[r3] = self.registers[r1] & self.registers[r2];
             }
-            Opcode::Call(imm) => {
-                self.stack_push(Register::PC).unwrap();
-                self.registers[Register::PC] = (imm.0 + 1) as u32;
+            Opcode::Or(r1, r2, r3) => {
+                self.registers[r3] = self.registers[r1] | self.registers[r2];
             }
-            Opcode::Fn => {
-                self.skipping_body = true;
+            Opcode::Xor(r1, r2, r3) => {
+                self.registers[r3] = self.registers[r1] ^ self.registers[r2];
             }
-            Opcode::StackAdd => {
-                let a = self.stack_pop_internal();
-                let b = self.stack_pop_internal();
-                self.stack_push_internal(b + a).unwrap();
+            Opcode::Not(r1, r2) => {
+                self.registers[r2] = !self.registers[r1];
             }
-            Opcode::StackSub => {
-                let a = self.stack_pop_internal();
-                let b = self.stack_pop_internal();
-                self.stack_push_internal(b - a).unwrap();
+            Opcode::Shl(r1, r2, r3) => {
+                self.registers[r3] = self.registers[r1].wrapping_shl(self.registers[r2]);
             }
-            Opcode::StackMul => {
-                let a = self.stack_pop_internal();
-                let b = self.stack_pop_internal();
-                self.stack_push_internal(b * a).unwrap();
+            Opcode::Shr(r1, r2, r3) => {
+                self.registers[r3] = self.registers[r1].wrapping_shr(self.registers[r2]);
             }
-            Opcode::StackDiv => {
-                let a = self.stack_pop_internal();
-                let b = self.stack_pop_internal();
-                self.stack_push_internal(b / a).unwrap();
-            }
-            Opcode::Syscall => match self.registers[Register::A] {
-                0 => {
-                    let code = self.registers[Register::B];
-                    return Ok(Some(code));
-                }
-                1 => {
-                    let _fd = self.registers[Register::B];
-                    let base_addr = self.registers[Register::C];
-                    let len = self.registers[Register::D];
-                    let saved_addr = self.registers[Register::SP];
-                    self.registers[Register::SP] = base_addr;
-                    let mut buf = String::new();
-
-                    let stdin = std::io::stdin();
-                    stdin.read_line(&mut buf).unwrap();
-
-                    for (i, c) in buf.bytes().enumerate() {
-                        if i >= len as usize {
-                            break;
-                        }
-                        self.registers[Register::A] = c as u32;
-                        self.stack_push(Register::A).unwrap();
-                    }
-                    self.registers[Register::SP] = saved_addr;
-                }
-                2 => {
-                    let _fd = self.registers[Register::B];
-                    let base_addr = self.registers[Register::C];
-                    let len = self.registers[Register::D];
-                    let saved_addr = self.registers[Register::SP];
-                    self.registers[Register::SP] = base_addr;
-
-                    for i in base_addr..base_addr + len {
-                        print!(
-                            "{}",
-                            char::from_u32(self.memory.read(i as usize).unwrap()).unwrap()
-                        );
-                    }
-                    self.registers[Register::SP] = saved_addr;
-                }
-                _ => {
-                    todo!()
+            Opcode::Sar(r1, r2, r3) => {
+                self.registers[r3] =
+                    (self.registers[r1] as i32).wrapping_shr(self.registers[r2]) as u32;
+            }
+            Opcode::StackAnd => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                self.stack_push_internal(b & a)?;
+            }
+            Opcode::StackOr => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                self.stack_push_internal(b | a)?;
+            }
+            Opcode::StackXor => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                self.stack_push_internal(b ^ a)?;
+            }
+            Opcode::StackNot => {
+                let a = self.stack_pop_internal()?;
+                self.stack_push_internal(!a)?;
+            }
+            Opcode::StackShl => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                self.stack_push_internal(b.wrapping_shl(a))?;
+            }
+            Opcode::StackShr => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                self.stack_push_internal(b.wrapping_shr(a))?;
+            }
+            Opcode::StackSar => {
+                let a = self.stack_pop_internal()?;
+                let b = self.stack_pop_internal()?;
+                self.stack_push_internal((b as i32).wrapping_shr(a) as u32)?;
+            }
+            Opcode::ImmLo(r1, imm) => {
+                self.registers[r1] = imm.into();
+            }
+            Opcode::ImmHi(r1, imm) => {
+                self.registers[r1] = (self.registers[r1] & 0xffff) | (u32::from(imm) << 16);
+            }
+            Opcode::IMul(r1, r2, r3) => {
+                let (result, overflow) =
+                    (self.registers[r1] as i32).overflowing_mul(self.registers[r2] as i32);
+                self.registers[r3] = self.with_flags((result as u32, overflow, overflow));
+            }
+            Opcode::IDiv(r1, r2, r3) => {
+                let (a, b) = (self.registers[r1] as i32, self.registers[r2] as i32);
+                if b == 0 {
+                    return Err(RuntimeError::DivisionByZero(pc));
                 }
-            },
+                // i32::MIN / -1 is the only quotient that doesn't fit
+                let result = a
+                    .checked_div(b)
+                    .ok_or(RuntimeError::ArithmeticOverflow(pc))?;
+                self.registers[r3] = self.with_flags((result as u32, false, false));
+            }
+            Opcode::SCmp(r1, r2) => {
+                let (a, b) = (self.registers[r1], self.registers[r2]);
+                let ordering = (a as i32).cmp(&(b as i32));
+                self.set_comparison_flags(Some(ordering), compare_flags(a, b));
+            }
+            Opcode::SImm(r1, imm) => {
+                self.registers[r1] = imm.sign_extend() as u32;
+            }
+            Opcode::FAdd(r1, r2, r3) => {
+                self.registers[r3] = (self.float(r1) + self.float(r2)).to_bits();
+            }
+            Opcode::FSub(r1, r2, r3) => {
+                self.registers[r3] = (self.float(r1) - self.float(r2)).to_bits();
+            }
+            Opcode::FMul(r1, r2, r3) => {
+                self.registers[r3] = (self.float(r1) * self.float(r2)).to_bits();
+            }
+            Opcode::FDiv(r1, r2, r3) => {
+                self.registers[r3] = (self.float(r1) / self.float(r2)).to_bits();
+            }
+            Opcode::FCmp(r1, r2) => {
+                let (a, b) = (self.float(r1), self.float(r2));
+                let zero = if a == 0.0 { flags::ZERO } else { 0 };
+                self.set_comparison_flags(a.partial_cmp(&b), zero);
+            }
+            Opcode::ItoF(r1, r2) => {
+                self.registers[r2] = (self.registers[r1] as i32 as f32).to_bits();
+            }
+            Opcode::FtoI(r1, r2) => {
+                self.registers[r2] = self.float(r1) as i32 as u32;
+            }
+            Opcode::Syscall => {
+                let number = self.registers[Register::A];
+                return self
+                    .syscalls
+                    .syscall(number, &mut self.registers, &mut self.memory)
+                    .map_err(|e| RuntimeError::Syscall(pc, e));
+            }
         }
 
         Ok(None)
     }
 
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn memory(&self) -> &Ram {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Ram {
+        &mut self.memory
+    }
+
+    pub fn program(&self) -> &Rom {
+        &self.program
+    }
+
+    /// The `Call`s that led to the current instruction, outermost first.
+    /// Tracked apart from the stack, so it survives the program overwriting its return addresses
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
     /// Used for debug purposes
     pub fn dump_state(&self) {
         eprintln!("{}", self.registers);
+        eprintln!("As floats:\n{}", self.registers.floats());
 
         if self.registers[Register::SP] == 0 {
             return;
         }
         eprintln!("Stack:");
-        for i in 0..self.registers[Register::SP] {
-            eprintln!("{}", self.memory.read(i as usize).unwrap());
+        for value in self
+            .memory
+            .get_data()
+            .iter()
+            .take(self.registers[Register::SP] as usize)
+        {
+            eprintln!("{}", value);
         }
     }
 }
+
+/// Wrapping arithmetic returning (result, carry, signed overflow)
+fn add(a: u32, b: u32) -> (u32, bool, bool) {
+    let (result, carry) = a.overflowing_add(b);
+    (result, carry, (a as i32).overflowing_add(b as i32).1)
+}
+
+fn sub(a: u32, b: u32) -> (u32, bool, bool) {
+    let (result, borrow) = a.overflowing_sub(b);
+    (result, borrow, (a as i32).overflowing_sub(b as i32).1)
+}
+
+/// Flags Cmp and SCmp set besides the comparison: Zero when the first operand is 0,
+/// Carry, Overflow and Sign as if the second one was subtracted from it
+fn compare_flags(a: u32, b: u32) -> u32 {
+    let (result, carry, overflow) = sub(a, b);
+    let mut flag = if a == 0 { flags::ZERO } else { 0 };
+    flag |= if carry { flags::CARRY } else { 0 };
+    flag |= if overflow { flags::OVERFLOW } else { 0 };
+    flag |= if result >> 31 == 1 { flags::SIGN } else { 0 };
+    flag
+}
+
+fn mul(a: u32, b: u32) -> (u32, bool, bool) {
+    let (result, carry) = a.overflowing_mul(b);
+    (result, carry, (a as i32).overflowing_mul(b as i32).1)
+}
@@ -0,0 +1,353 @@
+use crate::bytecode::{crc32, BytecodeError, SectionKind, ISA_VERSION, MIN_ISA_VERSION};
+
+/// First four bytes of every relocatable object file
+pub const OBJECT_MAGIC: [u8; 4] = *b"CROB";
+/// Version of the object layout described on [`Object`]
+pub const OBJECT_FORMAT_VERSION: u16 = 1;
+
+/// magic(4) format_version(2) isa_version(2) entry(4) checksum(4)
+const HEADER_SIZE: usize = 16;
+/// Stored in place of the entry point by objects without top level code
+const NO_ENTRY: u32 = u32::MAX;
+
+/// Local - Only visible inside its object
+/// Export - Defined here, visible to every object being linked
+/// Import - Defined by another object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Binding {
+    Local,
+    Export,
+    Import,
+}
+
+impl TryFrom<u8> for Binding {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Local),
+            1 => Ok(Self::Export),
+            2 => Ok(Self::Import),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub binding: Binding,
+    /// Section the symbol is defined in, None for imports
+    pub section: Option<SectionKind>,
+    /// Offset from the start of its section in this object
+    pub value: u32,
+}
+
+/// Which field of a word a relocation rewrites, and the range it accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RelocationKind {
+    /// Unsigned 13 bit literal of Imm, PushImm, Call and the jumps
+    Lit13,
+    /// Signed 13 bit literal of SImm
+    SignedLit13,
+    /// Signed 13 bit offset of Load and Store
+    Offset13,
+    /// 16 bit literal of ImmLo and ImmHi
+    Lit16,
+    /// Lower half of a 32 bit value, in the ImmLo of a Li
+    Lo16,
+    /// Upper half of a 32 bit value, in the ImmHi of a Li
+    Hi16,
+    /// A whole word of the data section
+    Word,
+}
+
+impl TryFrom<u8> for RelocationKind {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Lit13),
+            1 => Ok(Self::SignedLit13),
+            2 => Ok(Self::Offset13),
+            3 => Ok(Self::Lit16),
+            4 => Ok(Self::Lo16),
+            5 => Ok(Self::Hi16),
+            6 => Ok(Self::Word),
+            _ => Err(()),
+        }
+    }
+}
+
+impl RelocationKind {
+    /// Writes `value` into the field of `word`, None if it doesn't fit
+    pub fn patch(self, word: u32, value: i64) -> Option<u32> {
+        let (shift, mask, range) = match self {
+            Self::Lit13 => (12, 0x1fff, 0..=8191),
+            Self::SignedLit13 => (12, 0x1fff, -4096..=4095),
+            Self::Offset13 => (16, 0x1fff, -4096..=4095),
+            Self::Lit16 => (12, 0xffff, 0..=0xffff),
+            Self::Lo16 | Self::Hi16 | Self::Word => {
+                (0, u32::MAX, i32::MIN as i64..=u32::MAX as i64)
+            }
+        };
+        if !range.contains(&value) {
+            return None;
+        }
+
+        let (shift, mask, value) = match self {
+            Self::Lo16 => (12, 0xffff, value & 0xffff),
+            Self::Hi16 => (12, 0xffff, (value >> 16) & 0xffff),
+            _ => (shift, mask, value),
+        };
+        Some((word & !(mask << shift)) | (((value as u32) & mask) << shift))
+    }
+}
+
+/// What the value of a relocation is relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationTarget {
+    /// Start of one of this object's sections, for its own labels
+    Section(SectionKind),
+    /// Index into the symbol table, for imports
+    Symbol(u32),
+}
+
+/// A field that has to be rewritten once the target's address is known:
+/// the field gets the target's address plus the addend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// Section holding the word to rewrite
+    pub section: SectionKind,
+    /// Index of the word in its section
+    pub offset: u32,
+    pub kind: RelocationKind,
+    pub target: RelocationTarget,
+    pub addend: i32,
+}
+
+/// Output of assembling a single file, linked into an [`Executable`](crate::bytecode::Executable) by crlink
+///
+/// Everything is little-endian:
+/// ```text
+/// header:      magic "CROB" | format version u16 | ISA version u16 | entry u32 | checksum u32
+/// counts:      code words u32 | data words u32 | symbols u32 | relocations u32
+/// payload:     the code words, then the data words
+/// symbols:     binding u8 | section u8 | value u32 | name length u16 | UTF-8 name
+/// relocations: section u8 | kind u8 | target u8 | symbol u32 | offset u32 | addend i32
+/// ```
+/// The entry is `u32::MAX` when the object has no top level code, a symbol section
+/// of 255 marks an import and a relocation target of 0/1 is the code/data section, 2 a symbol.
+/// The checksum is the CRC-32 of everything after the header
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Object {
+    pub isa_version: u16,
+    /// Offset of the top level code in the code section
+    pub entry: Option<u32>,
+    pub code: Vec<u32>,
+    pub data: Vec<u32>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    /// Fails if a symbol name doesn't fit its 16 bit length field
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BytecodeError> {
+        let mut body = vec![];
+        for count in [
+            self.code.len(),
+            self.data.len(),
+            self.symbols.len(),
+            self.relocations.len(),
+        ] {
+            body.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+        for word in self.code.iter().chain(&self.data) {
+            body.extend_from_slice(&word.to_le_bytes());
+        }
+
+        for symbol in &self.symbols {
+            body.push(symbol.binding as u8);
+            body.push(symbol.section.map_or(u8::MAX, |s| s as u8));
+            body.extend_from_slice(&symbol.value.to_le_bytes());
+            let len = u16::try_from(symbol.name.len())
+                .map_err(|_| BytecodeError::NameTooLong(symbol.name.clone()))?;
+            body.extend_from_slice(&len.to_le_bytes());
+            body.extend_from_slice(symbol.name.as_bytes());
+        }
+
+        for relocation in &self.relocations {
+            let (target, symbol) = match relocation.target {
+                RelocationTarget::Section(section) => (section as u8, 0),
+                RelocationTarget::Symbol(index) => (2, index),
+            };
+            body.push(relocation.section as u8);
+            body.push(relocation.kind as u8);
+            body.push(target);
+            body.extend_from_slice(&symbol.to_le_bytes());
+            body.extend_from_slice(&relocation.offset.to_le_bytes());
+            body.extend_from_slice(&relocation.addend.to_le_bytes());
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&OBJECT_MAGIC);
+        bytes.extend_from_slice(&OBJECT_FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.isa_version.to_le_bytes());
+        bytes.extend_from_slice(&self.entry.unwrap_or(NO_ENTRY).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(4)? != OBJECT_MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+
+        let format_version = reader.u16()?;
+        if format_version != OBJECT_FORMAT_VERSION {
+            return Err(BytecodeError::UnsupportedFormatVersion(format_version));
+        }
+        let isa_version = reader.u16()?;
+        if !(MIN_ISA_VERSION..=ISA_VERSION).contains(&isa_version) {
+            return Err(BytecodeError::UnsupportedIsaVersion(isa_version));
+        }
+        let entry = Some(reader.u32()?).filter(|entry| *entry != NO_ENTRY);
+        let checksum = reader.u32()?;
+
+        let actual = crc32(&bytes[HEADER_SIZE..]);
+        if actual != checksum {
+            return Err(BytecodeError::ChecksumMismatch {
+                expected: checksum,
+                found: actual,
+            });
+        }
+
+        let code_len = reader.u32()? as usize;
+        let data_len = reader.u32()? as usize;
+        let symbol_count = reader.u32()? as usize;
+        let relocation_count = reader.u32()? as usize;
+
+        let code = reader.words(code_len)?;
+        let data = reader.words(data_len)?;
+
+        let mut symbols = vec![];
+        for i in 0..symbol_count {
+            let binding =
+                Binding::try_from(reader.u8()?).map_err(|_| BytecodeError::InvalidSymbol(i))?;
+            let section = match reader.u8()? {
+                u8::MAX => None,
+                section => Some(
+                    SectionKind::try_from(section as u32)
+                        .map_err(|_| BytecodeError::InvalidSymbol(i))?,
+                ),
+            };
+            let value = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| BytecodeError::InvalidSymbol(i))?;
+            if (binding == Binding::Import) != section.is_none() {
+                return Err(BytecodeError::InvalidSymbol(i));
+            }
+
+            symbols.push(Symbol {
+                name,
+                binding,
+                section,
+                value,
+            });
+        }
+
+        let mut relocations = vec![];
+        for i in 0..relocation_count {
+            let section = SectionKind::try_from(reader.u8()? as u32)
+                .map_err(|_| BytecodeError::InvalidRelocation(i))?;
+            let kind = RelocationKind::try_from(reader.u8()?)
+                .map_err(|_| BytecodeError::InvalidRelocation(i))?;
+            let target = reader.u8()?;
+            let symbol = reader.u32()?;
+            let target = match target {
+                0 => RelocationTarget::Section(SectionKind::Code),
+                1 => RelocationTarget::Section(SectionKind::Data),
+                2 if (symbol as usize) < symbols.len() => RelocationTarget::Symbol(symbol),
+                _ => return Err(BytecodeError::InvalidRelocation(i)),
+            };
+            let offset = reader.u32()?;
+            let addend = reader.u32()? as i32;
+
+            let section_len = match section {
+                SectionKind::Code => code.len(),
+                SectionKind::Data => data.len(),
+            };
+            if offset as usize >= section_len {
+                return Err(BytecodeError::InvalidRelocation(i));
+            }
+
+            relocations.push(Relocation {
+                section,
+                offset,
+                kind,
+                target,
+                addend,
+            });
+        }
+
+        if reader.position != bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+
+        Ok(Self {
+            isa_version,
+            entry,
+            code,
+            data,
+            symbols,
+            relocations,
+        })
+    }
+}
+
+/// Reads the fields of an object or archive file in order
+pub(crate) struct Reader<'a> {
+    pub bytes: &'a [u8],
+    pub position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, BytecodeError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn words(&mut self, count: usize) -> Result<Vec<u32>, BytecodeError> {
+        let len = count.checked_mul(4).ok_or(BytecodeError::Truncated)?;
+        Ok(self
+            .take(len)?
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect())
+    }
+}
@@ -3,10 +3,11 @@ use std::ops::{Index, IndexMut};
 
 use macros::RegisterTraits;
 
-/// A, B, C, D - General purpose registers
+/// A, B, C, D, E, F, G, H - General purpose registers
 /// SP - Stack pointer
 /// PC - Program pointer,
-/// Flag - Flags (over/underflow, comparisons)
+/// FP - Frame pointer, maintained by Call and Ret
+/// Flag - Flags (carry/overflow, comparisons), see [`flags`]
 /// Zero - Always zero, writing to this does nothing
 /// Count - Never used by the program
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Copy, RegisterTraits)]
@@ -16,13 +17,39 @@ pub enum Register {
     PC,
     Flag,
     Zero,
+    FP,
     A,
     B,
     C,
     D,
+    E,
+    F,
+    G,
+    H,
     Count,
 }
 
+/// Bits of the Flag register
+///
+/// Cmp sets ZERO when its first operand is zero, the comparison bits,
+/// and CARRY, OVERFLOW and SIGN as if it subtracted the second operand from the first.
+/// Arithmetic sets ZERO, CARRY, OVERFLOW and SIGN from its result
+pub mod flags {
+    pub const ZERO: u32 = 1 << 0;
+    pub const LESS: u32 = 1 << 1;
+    pub const GREATER: u32 = 1 << 2;
+    pub const EQUAL: u32 = 1 << 3;
+    pub const NOT_EQUAL: u32 = 1 << 4;
+    /// Unsigned overflow, or a borrow for subtraction
+    pub const CARRY: u32 = 1 << 5;
+    /// Signed overflow
+    pub const OVERFLOW: u32 = 1 << 6;
+    /// Highest bit of the result
+    pub const SIGN: u32 = 1 << 7;
+
+    pub const ARITHMETIC: u32 = ZERO | CARRY | OVERFLOW | SIGN;
+}
+
 #[derive(Debug)]
 pub struct InvalidRegister;
 
@@ -35,10 +62,15 @@ impl TryFrom<&str> for Register {
             "PC" => Ok(Self::PC),
             "Flag" => Ok(Self::Flag),
             "Zero" => Ok(Self::Zero),
+            "FP" => Ok(Self::FP),
             "A" => Ok(Self::A),
             "B" => Ok(Self::B),
             "C" => Ok(Self::C),
             "D" => Ok(Self::D),
+            "E" => Ok(Self::E),
+            "F" => Ok(Self::F),
+            "G" => Ok(Self::G),
+            "H" => Ok(Self::H),
             _ => Err(InvalidRegister),
         }
     }
@@ -54,21 +86,51 @@ impl Registers {
             registers: [0; Register::Count as usize],
         }
     }
+
+    /// The general purpose registers interpreted as f32s, for display
+    pub fn floats(&self) -> FloatRegisters<'_> {
+        FloatRegisters(self)
+    }
+}
+
+pub struct FloatRegisters<'a>(&'a Registers);
+
+impl fmt::Display for FloatRegisters<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Register::*;
+        for (i, r) in [A, B, C, D, E, F, G, H].into_iter().enumerate() {
+            if i % 4 == 0 && i > 0 {
+                writeln!(f)?;
+            } else if i > 0 {
+                write!(f, "   ")?;
+            }
+            write!(f, "{}: {:?}", r, f32::from_bits(self.0[r]))?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Registers {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Register::*;
-        writeln!(
-            f,
-            "SP: {} PC: {} Flag: {:032b}",
-            self[SP], self[PC], self[Flag]
-        )?;
         write!(
             f,
-            "A: {}   B: {}   C: {}   D: {}",
-            self[A], self[B], self[C], self[D]
-        )
+            "SP: {} FP: {} PC: {} Flag: {:032b}",
+            self[SP], self[FP], self[PC], self[Flag]
+        )?;
+        for (i, r) in [A, B, C, D, E, F, G, H].into_iter().enumerate() {
+            if i % 4 == 0 {
+                writeln!(f)?;
+            } else {
+                write!(f, "   ")?;
+            }
+            write!(f, "{}: {}", r, self[r])?;
+            // Show what a negative value would be
+            if (self[r] as i32) < 0 {
+                write!(f, " ({})", self[r] as i32)?;
+            }
+        }
+        Ok(())
     }
 }
 
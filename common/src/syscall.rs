@@ -0,0 +1,139 @@
+use core::fmt;
+use std::io::{Read, Stdin, Stdout, Write};
+
+use crate::data_structures::{error::OutOfBoundsError, ram::Ram};
+use crate::registers::{Register, Registers};
+
+pub const SYS_EXIT: u32 = 0;
+pub const SYS_READ: u32 = 1;
+pub const SYS_WRITE: u32 = 2;
+
+#[derive(Debug)]
+pub enum SyscallError {
+    Unknown(u32),
+    OutOfBounds(OutOfBoundsError),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyscallError::Unknown(n) => write!(f, "Unknown syscall {}", n),
+            SyscallError::OutOfBounds(e) => write!(f, "Syscall buffer out of bounds: {}", e),
+            SyscallError::Io(e) => write!(f, "Syscall I/O failed: {}", e),
+        }
+    }
+}
+
+impl From<OutOfBoundsError> for SyscallError {
+    fn from(value: OutOfBoundsError) -> Self {
+        SyscallError::OutOfBounds(value)
+    }
+}
+
+/// Executes the `Syscall` instruction on behalf of the VM
+///
+/// `number` is the value of the A register, arguments are read from the other
+/// registers. Returning `Ok(Some(code))` stops the machine with that exit code.
+/// To add syscalls on top of the standard ones, match on your own numbers and
+/// forward everything else to a [`StdSyscalls`]
+pub trait SyscallHandler {
+    fn syscall(
+        &mut self,
+        number: u32,
+        registers: &mut Registers,
+        memory: &mut Ram,
+    ) -> Result<Option<u32>, SyscallError>;
+}
+
+/// The standard syscalls, reading from `input` and writing to `output`
+///
+/// sys_exit  (0): B - exit code
+/// sys_read  (1): B - file descriptor, C - buffer address, D - buffer length,
+///                reads one line, returns the number of words written in A
+/// sys_write (2): B - file descriptor, C - buffer address, D - buffer length,
+///                each word is written as a unicode character
+pub struct StdSyscalls<R: Read, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: Read, W: Write> StdSyscalls<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    /// Reads up to and including the next newline, one byte at a time so
+    /// nothing past the line gets consumed from a shared input
+    fn read_line(&mut self) -> Result<Vec<u8>, SyscallError> {
+        let mut line = vec![];
+        let mut byte = [0];
+
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) => {
+                    line.push(byte[0]);
+                    if byte[0] == b'\n' {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(SyscallError::Io(e)),
+            }
+        }
+
+        Ok(line)
+    }
+}
+
+impl Default for StdSyscalls<Stdin, Stdout> {
+    fn default() -> Self {
+        Self::new(std::io::stdin(), std::io::stdout())
+    }
+}
+
+impl<R: Read, W: Write> SyscallHandler for StdSyscalls<R, W> {
+    fn syscall(
+        &mut self,
+        number: u32,
+        registers: &mut Registers,
+        memory: &mut Ram,
+    ) -> Result<Option<u32>, SyscallError> {
+        match number {
+            SYS_EXIT => Ok(Some(registers[Register::B])),
+            SYS_READ => {
+                let _fd = registers[Register::B];
+                let base_addr = registers[Register::C] as usize;
+                let len = registers[Register::D] as usize;
+
+                let line = self.read_line()?;
+                let n = line.len().min(len);
+                for (i, c) in line.iter().take(n).enumerate() {
+                    memory.write(*c as u32, base_addr + i)?;
+                }
+
+                registers[Register::A] = n as u32;
+                Ok(None)
+            }
+            SYS_WRITE => {
+                let _fd = registers[Register::B];
+                let base_addr = registers[Register::C] as usize;
+                let len = registers[Register::D] as usize;
+
+                let text: String = memory
+                    .read_many(base_addr, len)?
+                    .into_iter()
+                    .map(|c| char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                self.output
+                    .write_all(text.as_bytes())
+                    .and_then(|_| self.output.flush())
+                    .map_err(SyscallError::Io)?;
+
+                Ok(None)
+            }
+            _ => Err(SyscallError::Unknown(number)),
+        }
+    }
+}
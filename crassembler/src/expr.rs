@@ -0,0 +1,332 @@
+use common::bytecode::SectionKind;
+use common::instructions::parse_literal;
+
+/// Why an operand expression couldn't be evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExprError {
+    UnknownSymbol,
+    InvalidLiteral,
+    InvalidCharacter,
+    UnexpectedEnd,
+    UnexpectedToken,
+    DivisionByZero,
+    Overflow,
+    NotRelocatable,
+}
+
+impl ExprError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ExprError::UnknownSymbol => "Unknown label",
+            ExprError::InvalidLiteral => "Invalid number literal",
+            ExprError::InvalidCharacter => "Invalid character literal",
+            ExprError::UnexpectedEnd => "Unexpected end of expression",
+            ExprError::UnexpectedToken => "Unexpected token in expression",
+            ExprError::DivisionByZero => "Division by zero in expression",
+            ExprError::Overflow => "Expression overflows",
+            ExprError::NotRelocatable => {
+                "Labels can only be added to or subtracted from constants, or subtracted from each other"
+            }
+        }
+    }
+}
+
+/// What a label is relative to, its address is only known once the program is linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    Section(SectionKind),
+    Import(String),
+}
+
+/// `offset` plus the final address of `base`, if there is one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Value {
+    pub offset: i64,
+    pub base: Option<Base>,
+}
+
+impl Value {
+    pub fn constant(offset: i64) -> Self {
+        Self { offset, base: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 11] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~"];
+
+fn lex(source: &str) -> Result<Vec<Token>, ExprError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let rest: String = chars[i..].iter().collect();
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            i += 1;
+        } else if c == '\'' {
+            let (value, len) = char_literal(&chars[i..])?;
+            tokens.push(Token::Number(value));
+            i += len;
+        } else if c.is_ascii_digit() || c == '#' || c == '$' {
+            let len = 1 + chars[i + 1..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .count();
+            let literal: String = chars[i..i + len].iter().collect();
+            let value = parse_literal(&literal).map_err(|_| ExprError::InvalidLiteral)?;
+            tokens.push(Token::Number(value));
+            i += len;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                .count();
+            tokens.push(Token::Symbol(chars[i..i + len].iter().collect()));
+            i += len;
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            i += op.len();
+        } else {
+            return Err(ExprError::UnexpectedToken);
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// `'A'` or an escaped `'\n'`, returns the code point and the length in chars
+fn char_literal(chars: &[char]) -> Result<(i64, usize), ExprError> {
+    let (c, len) = match chars.get(1..) {
+        Some(['\\', escape, '\'', ..]) => {
+            let c = match escape {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                '\\' => '\\',
+                '\'' => '\'',
+                _ => return Err(ExprError::InvalidCharacter),
+            };
+            (c, 4)
+        }
+        Some([c, '\'', ..]) if *c != '\\' && *c != '\'' => (*c, 3),
+        _ => return Err(ExprError::InvalidCharacter),
+    };
+
+    Ok((c as i64, len))
+}
+
+/// Binary operators from the loosest to the tightest binding
+const PRECEDENCE: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    lookup: &'a dyn Fn(&str) -> Option<Value>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ExprError> {
+        let token = self.peek().cloned().ok_or(ExprError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, ExprError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value, ExprError> {
+        match self.next()? {
+            Token::Op("-") => self
+                .constant()?
+                .checked_neg()
+                .map(Value::constant)
+                .ok_or(ExprError::Overflow),
+            Token::Op("+") => self.unary(),
+            // Bitwise not of the 32 bit value
+            Token::Op("~") => Ok(Value::constant(!self.constant()? & 0xffff_ffff)),
+            Token::Number(value) => Ok(Value::constant(value)),
+            Token::Symbol(name) => (self.lookup)(&name).ok_or(ExprError::UnknownSymbol),
+            Token::Open => {
+                let value = self.binary(0)?;
+                match self.next()? {
+                    Token::Close => Ok(value),
+                    _ => Err(ExprError::UnexpectedToken),
+                }
+            }
+            _ => Err(ExprError::UnexpectedToken),
+        }
+    }
+
+    /// Operand of a unary operator that only makes sense for constants
+    fn constant(&mut self) -> Result<i64, ExprError> {
+        match self.unary()? {
+            Value { offset, base: None } => Ok(offset),
+            _ => Err(ExprError::NotRelocatable),
+        }
+    }
+}
+
+/// Relocatable values survive `label + constant`, `constant + label`, `label - constant`
+/// and `label - label` in the same section, everything else needs constants
+fn apply(op: &str, lhs: Value, rhs: Value) -> Result<Value, ExprError> {
+    let base = match (op, lhs.base, rhs.base) {
+        (_, None, None) => None,
+        ("+", base @ Some(_), None) | ("+", None, base @ Some(_)) | ("-", base @ Some(_), None) => {
+            base
+        }
+        ("-", Some(a), Some(b)) if a == b => None,
+        _ => return Err(ExprError::NotRelocatable),
+    };
+
+    Ok(Value {
+        offset: apply_constant(op, lhs.offset, rhs.offset)?,
+        base,
+    })
+}
+
+fn apply_constant(op: &str, lhs: i64, rhs: i64) -> Result<i64, ExprError> {
+    let result = match op {
+        "+" => lhs.checked_add(rhs),
+        "-" => lhs.checked_sub(rhs),
+        "*" => lhs.checked_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err(ExprError::DivisionByZero),
+        "/" => lhs.checked_div(rhs),
+        "%" => lhs.checked_rem(rhs),
+        "&" => Some(lhs & rhs),
+        "|" => Some(lhs | rhs),
+        "^" => Some(lhs ^ rhs),
+        "<<" => u32::try_from(rhs)
+            .ok()
+            .and_then(|rhs| lhs.checked_shl(rhs))
+            .filter(|result| result >> rhs == lhs),
+        ">>" => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs)),
+        _ => unreachable!(),
+    };
+
+    result.ok_or(ExprError::Overflow)
+}
+
+/// Evaluates a constant expression like `(size * 2 + 1)`, `end - start` or `'A' | 0x20`
+///
+/// Symbols are resolved through `lookup`. The result is not range checked,
+/// that is up to the field the value ends up in
+pub fn evaluate(source: &str, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, ExprError> {
+    let lookup = |name: &str| lookup(name).map(Value::constant);
+    evaluate_relocatable(source, &lookup).map(|value| value.offset)
+}
+
+/// Like [`evaluate`], for operands that can refer to labels whose address isn't known yet
+pub fn evaluate_relocatable(
+    source: &str,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value, ExprError> {
+    let mut parser = Parser {
+        tokens: lex(source)?,
+        position: 0,
+        lookup,
+    };
+
+    let value = parser.binary(0)?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => Err(ExprError::UnexpectedToken),
+    }
+}
+
+/// Replaces the names in an operand through `replace`, skipping quoted text and
+/// number literals. Replacements that are expressions themselves get parentheses
+/// so they keep their meaning inside a larger expression
+pub fn substitute(operand: &str, replace: impl Fn(&str) -> Option<String>) -> String {
+    if let Some(value) = replace(operand) {
+        return value;
+    }
+
+    let chars: Vec<char> = operand.chars().collect();
+    let mut result = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let name_start = c.is_ascii_alphabetic() || c == '_' || c == '@';
+        let in_number =
+            i > 0 && (chars[i - 1].is_ascii_alphanumeric() || "#$".contains(chars[i - 1]));
+
+        match quote {
+            Some(q) if c == q && !escaped => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if name_start && !in_number => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '@')
+                    .count();
+                let name: String = chars[i..i + len].iter().collect();
+                match replace(&name) {
+                    Some(value)
+                        if value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "_@.#$".contains(c)) =>
+                    {
+                        result.push_str(&value)
+                    }
+                    Some(value) => result.push_str(&format!("({})", value)),
+                    None => result.push_str(&name),
+                }
+                i += len;
+                continue;
+            }
+            None => {}
+        }
+
+        escaped = quote.is_some() && c == '\\' && !escaped;
+        result.push(c);
+        i += 1;
+    }
+
+    result
+}
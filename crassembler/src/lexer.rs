@@ -0,0 +1,166 @@
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+/// A file being assembled, shared by all of its tokens
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+    /// The file and line of the `include` that pulled this file in
+    pub included_from: Option<(Rc<SourceFile>, u32)>,
+}
+
+impl SourceFile {
+    pub fn new(name: String, source: String) -> Rc<Self> {
+        Rc::new(Self {
+            name,
+            source,
+            included_from: None,
+        })
+    }
+
+    /// A file pulled in by the `include` at `span`
+    pub fn included(name: String, source: String, span: &Span) -> Rc<Self> {
+        Rc::new(Self {
+            name,
+            source,
+            included_from: Some((span.file.clone(), span.line)),
+        })
+    }
+
+    /// Text of a line without its line break, lines count from 0
+    pub fn line(&self, line: u32) -> &str {
+        let text = self.source.split('\n').nth(line as usize).unwrap_or("");
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    /// Whether `path` is this file or one of the files including it
+    pub fn in_include_chain(&self, path: &Path) -> bool {
+        let same = Path::new(&self.name)
+            .canonicalize()
+            .is_ok_and(|p| path.canonicalize().is_ok_and(|path| p == path));
+        same || self
+            .included_from
+            .as_ref()
+            .is_some_and(|(parent, _)| parent.in_include_chain(path))
+    }
+}
+
+/// Where a token was written. Line, column and length are in bytes and count from 0
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub file: Rc<SourceFile>,
+    pub line: u32,
+    pub column: u32,
+    pub len: u32,
+    /// The macro invocation the token was expanded from
+    pub expanded_from: Option<Rc<Span>>,
+}
+
+/// `file:line:col`, counting from 1 like editors do
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.file.name,
+            self.line + 1,
+            self.column + 1
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub value: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Line(pub Vec<Token>);
+
+/// Splits a file into lines of tokens. `;` starts a comment anywhere outside a quote,
+/// lines left without tokens are dropped
+pub fn tokenize(file: &Rc<SourceFile>) -> Vec<Line> {
+    let mut lines = vec![];
+
+    for (y, text) in file.source.split('\n').enumerate() {
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let mut tokens = vec![];
+        let mut x = 0;
+
+        while let Some(c) = text[x..].chars().next() {
+            if c == ';' {
+                break;
+            }
+            if c.is_whitespace() {
+                x += c.len_utf8();
+                continue;
+            }
+
+            let end = token_end(text, x);
+            tokens.push(Token {
+                value: text[x..end].to_string(),
+                span: Span {
+                    file: file.clone(),
+                    line: y as u32,
+                    column: x as u32,
+                    len: (end - x) as u32,
+                    expanded_from: None,
+                },
+            });
+            x = end;
+        }
+
+        if !tokens.is_empty() {
+            lines.push(Line(tokens));
+        }
+    }
+
+    lines
+}
+
+/// The code of a line, without its comment and the whitespace around it
+pub fn strip_comment(text: &str) -> &str {
+    let mut x = 0;
+    let mut end = 0;
+    while let Some(c) = text[x..].chars().next() {
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() {
+            x += c.len_utf8();
+            continue;
+        }
+        end = token_end(text, x);
+        x = end;
+    }
+
+    text[..end].trim_start()
+}
+
+/// Byte index right after the token starting at `start`.
+/// Quoted strings, character literals and parenthesised expressions
+/// are a single token, whitespace included
+fn token_end(text: &str, start: usize) -> usize {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+
+    for (i, c) in text[start..].char_indices() {
+        if quote.is_none() && (c == ';' || (c.is_whitespace() && depth == 0)) {
+            return start + i;
+        }
+        match quote {
+            Some(q) if c == q && !escaped => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '(' => depth += 1,
+            None if c == ')' => depth -= 1,
+            _ => {}
+        }
+        escaped = quote.is_some() && c == '\\' && !escaped;
+    }
+
+    text.len()
+}
@@ -0,0 +1,188 @@
+use common::{
+    bytecode::SectionKind,
+    debug_info::{DebugInfo, DebugSymbol, LineEntry},
+    object::{Binding, Symbol},
+};
+
+use std::rc::Rc;
+
+use crate::lexer::{strip_comment, Span};
+
+/// Words shown on a row of the listing, longer lines continue on the next rows
+const WORDS_PER_ROW: usize = 2;
+
+/// A line that made it into the program, with what it assembled to
+#[derive(Debug)]
+pub struct ListingLine {
+    pub section: SectionKind,
+    pub address: usize,
+    /// Empty for lines with only a label
+    pub words: Vec<u32>,
+    /// Where the first token of the line was written
+    pub span: Span,
+    /// The tokens after definitions and macro parameters were substituted
+    pub text: String,
+}
+
+/// Every assembled line next to its address and encoding, followed by the symbols.
+/// Lines are in address order, so macro bodies show up indented under the invocation
+/// they were expanded from and functions after the top level code
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+    /// Names of the `Fn` definitions
+    pub functions: Vec<String>,
+    /// `%` definitions with their final value, sorted by name
+    pub defines: Vec<(String, String)>,
+}
+
+impl Listing {
+    /// Writes the listing, with the symbol table of the assembled program at the end
+    pub fn render(&self, symbols: &[Symbol]) -> String {
+        let mut out = String::new();
+        let location = |span: &Span| format!("{}:{}", span.file.name, span.line + 1);
+        let width = self
+            .lines
+            .iter()
+            .flat_map(|line| {
+                let invoked = invocations(&line.span).into_iter().map(|span| &**span);
+                invoked.chain([&line.span])
+            })
+            .map(|span| location(span).len())
+            .max()
+            .unwrap_or(0);
+        let blank_words = " ".repeat(WORDS_PER_ROW * 9 - 1);
+
+        let mut section = None;
+        let mut open_invocations: Vec<&Rc<Span>> = vec![];
+        for line in &self.lines {
+            if section != Some(line.section) {
+                out.push_str(&format!("{:?}\n", line.section));
+                section = Some(line.section);
+                open_invocations.clear();
+            }
+
+            // The invocation goes above the first line of every macro expansion
+            let invoked = invocations(&line.span);
+            let shared = open_invocations
+                .iter()
+                .zip(&invoked)
+                .take_while(|(a, b)| Rc::ptr_eq(a, b))
+                .count();
+            for (depth, span) in invoked.iter().enumerate().skip(shared) {
+                out.push_str(&format!(
+                    "{:04x}  {}  {:<width$}  {}{}\n",
+                    line.address,
+                    blank_words,
+                    location(span),
+                    "  ".repeat(depth),
+                    strip_comment(span.file.line(span.line)),
+                ));
+            }
+            open_invocations = invoked;
+
+            let indent = "  ".repeat(open_invocations.len());
+            let source = strip_comment(line.span.file.line(line.span.line));
+            let mut rows = line.words.chunks(WORDS_PER_ROW).map(|words| {
+                words
+                    .iter()
+                    .map(|w| format!("{:08x}", w))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            });
+            let words = rows.next().unwrap_or_default();
+            out.push_str(&format!(
+                "{:04x}  {:<words_width$}  {:<width$}  {}{}\n",
+                line.address,
+                words,
+                location(&line.span),
+                indent,
+                source,
+                words_width = blank_words.len(),
+            ));
+
+            // Substituted definitions and macro parameters, labels aren't expanded
+            let expanded = line.text.split_whitespace().ne(source.split_whitespace());
+            if !line.words.is_empty() && expanded {
+                out.push_str(&format!(
+                    "      {}  {:width$}  {}= {}\n",
+                    blank_words, "", indent, line.text
+                ));
+            }
+            for (i, words) in rows.enumerate() {
+                let address = line.address + (i + 1) * WORDS_PER_ROW;
+                out.push_str(&format!("{:04x}  {}\n", address, words));
+            }
+        }
+
+        out.push_str("\nSymbols\n");
+        for symbol in symbols {
+            let kind = match (symbol.section, self.functions.contains(&symbol.name)) {
+                (None, _) => "import".to_string(),
+                (Some(_), true) => "function".to_string(),
+                (Some(section), false) => format!("{:?} label", section).to_lowercase(),
+            };
+            let address = match symbol.section {
+                Some(_) => format!("{:04x}", symbol.value),
+                None => "----".to_string(),
+            };
+            let exported = match symbol.binding {
+                Binding::Export => ", exported",
+                _ => "",
+            };
+            out.push_str(&format!(
+                "{}  {:<16} {}{}\n",
+                address, symbol.name, kind, exported
+            ));
+        }
+
+        if !self.defines.is_empty() {
+            out.push_str("\nDefines\n");
+            for (name, value) in &self.defines {
+                out.push_str(&format!("{:<16} {}\n", name, value));
+            }
+        }
+
+        out
+    }
+
+    /// Where every instruction was written and the names of the code labels
+    pub fn debug_info(&self, symbols: &[Symbol]) -> DebugInfo {
+        let mut info = DebugInfo::default();
+        for line in &self.lines {
+            if line.section != SectionKind::Code || line.words.is_empty() {
+                continue;
+            }
+            let entry = LineEntry {
+                address: line.address as u32,
+                file: info.file_index(&line.span.file.name),
+                line: line.span.line + 1,
+                column: line.span.column + 1,
+            };
+            info.lines.push(entry);
+        }
+        info.symbols = symbols
+            .iter()
+            .filter(|s| s.section == Some(SectionKind::Code))
+            .map(|s| DebugSymbol {
+                name: s.name.clone(),
+                address: s.value,
+            })
+            .collect();
+        info.sort();
+
+        info
+    }
+}
+
+/// The macro invocations `span` was expanded from, the one written in the source first
+fn invocations(span: &Span) -> Vec<&Rc<Span>> {
+    let mut invocations = vec![];
+    let mut current = span.expanded_from.as_ref();
+    while let Some(invocation) = current {
+        invocations.push(invocation);
+        current = invocation.expanded_from.as_ref();
+    }
+    invocations.reverse();
+    invocations
+}
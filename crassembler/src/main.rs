@@ -1,13 +1,28 @@
+mod expr;
+mod lexer;
+mod listing;
+mod tests;
+
 use clap::Parser;
 use core::fmt;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{cmp::Ordering, error::Error, io::Write};
 
 use common::{
-    instructions::{Bit13Literal, Opcode},
+    bytecode::{Executable, Section, SectionKind, ISA_VERSION},
+    debug_info::sidecar_path,
+    instructions::{Bit13Literal, Bit16Literal, InvalidLiteralError, Opcode},
+    machine::DEFAULT_MEMORY_SIZE,
+    object::{Binding, Object, Relocation, RelocationKind, RelocationTarget, Symbol},
     registers::Register,
 };
+use expr::{Base, ExprError, Value};
+use lexer::{tokenize, Line, SourceFile, Token};
+use listing::{Listing, ListingLine};
 
 // Casm assembler for the crazyVM VM
 #[derive(Parser, Debug)]
@@ -24,363 +39,1395 @@ struct Args {
     /// Dissasemble the file?
     #[arg(short, long, default_value_t = false)]
     dissasemble: bool,
+
+    /// Write (or dissasemble) the old hex text format instead of binary bytecode
+    #[arg(long, default_value_t = false)]
+    legacy_hex: bool,
+
+    /// Directory to search for included files, after the including file's own directory
+    #[arg(short = 'I', long = "include")]
+    include_paths: Vec<PathBuf>,
+
+    /// Memory the program runs with, the data section has to fit in it
+    #[arg(short, long = "mem", default_value_t = DEFAULT_MEMORY_SIZE)]
+    memory_size: usize,
+
+    /// Write a relocatable object file for crlink instead of an executable
+    #[arg(short = 'c', long = "object", default_value_t = false)]
+    object: bool,
+
+    /// Also write a listing of every line with its address and encoding, and the symbol table
+    #[arg(long)]
+    listing: Option<String>,
+
+    /// Also write debug info for the VM and debugger, next to the output in `<output>.dbg`
+    #[arg(short = 'g', long = "debug-info", default_value_t = false)]
+    debug_info: bool,
 }
 
-struct CompError(Line, u32, &'static str, String);
+/// Output of the assembler: the instructions, the words of the data section
+/// and the warnings that didn't stop it. Addresses are relative to the start
+/// of their section, the relocations say which ones change when linking
+#[derive(Debug)]
+struct Program {
+    code: Vec<u32>,
+    data: Vec<u32>,
+    warnings: Vec<Diagnostic>,
+    /// Start of the top level code, None if the file only has functions and data
+    entry: Option<u32>,
+    symbols: Vec<Symbol>,
+    relocations: Vec<Relocation>,
+    listing: Listing,
+}
 
-impl fmt::Display for CompError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (x, _) = (self.0 .0[self.1 as usize].x, self.0 .0[self.1 as usize].y);
-        writeln!(f, "{}:", self.3)?;
-        writeln!(f, "{}", self.2)?;
-        writeln!(f, "   {}", self.0)?;
-        write!(f, "   ")?;
-        for _ in 0..x + 1 {
-            write!(f, " ")?;
+impl Program {
+    fn to_object(&self) -> Object {
+        Object {
+            isa_version: ISA_VERSION,
+            entry: self.entry,
+            code: self.code.clone(),
+            data: self.data.clone(),
+            symbols: self.symbols.clone(),
+            relocations: self.relocations.clone(),
         }
-        write!(f, "^")?;
+    }
+}
 
-        Ok(())
+#[derive(Debug)]
+struct CompError(Line, u32, &'static str);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
     }
 }
 
-#[derive(Clone)]
-struct Token {
-    value: String,
-    x: u32,
-    y: u32,
+#[derive(Debug)]
+struct Diagnostic {
+    severity: Severity,
+    error: CompError,
 }
 
-fn token_from_line(line: &str, x: &mut usize, y: u32) -> Token {
-    let mut buffer = String::new();
-    let begin = *x;
+/// Everything the assembler found, in the order it was found.
+/// Assembly goes on after an error so a single run reports all of them
+#[derive(Debug, Default)]
+struct Diagnostics(Vec<Diagnostic>);
 
-    while let Some(c) = line.chars().nth(*x) {
-        if c.is_whitespace() {
-            break;
-        }
+impl Diagnostics {
+    fn error(&mut self, error: CompError) {
+        self.0.push(Diagnostic {
+            severity: Severity::Error,
+            error,
+        });
+    }
+
+    fn warning(&mut self, error: CompError) {
+        self.0.push(Diagnostic {
+            severity: Severity::Warning,
+            error,
+        });
+    }
 
-        buffer.push(c);
-        *x += 1;
+    fn count(&self, severity: Severity) -> usize {
+        self.0.iter().filter(|d| d.severity == severity).count()
     }
 
-    Token {
-        value: buffer,
-        x: begin as u32,
-        y,
+    fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
     }
 }
 
-#[derive(Clone)]
-struct Line(Vec<Token>);
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
 
-impl fmt::Display for Line {
+impl fmt::Display for Diagnostic {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.0
-                .iter()
-                .fold(String::new(), |mut prev, curr| {
-                    prev.push_str(format!(" {}", curr.value).as_str());
-                    prev
-                })
-                .to_owned()
-        )
+        let CompError(line, idx, msg) = &self.error;
+        let span = &line.0[*idx as usize].span;
+        writeln!(f, "{}: {}: {}", span, self.severity, msg)?;
+
+        // The line as written, with the token underlined. Tabs are kept so the marker lines up
+        let text = span.file.line(span.line);
+        let indent: String = text
+            .get(..span.column as usize)
+            .unwrap_or("")
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(f, "   {}", text)?;
+        write!(f, "   {}^", indent)?;
+        for _ in 1..span.len {
+            write!(f, "~")?;
+        }
+
+        let mut file = &span.file;
+        while let Some((parent, line)) = &file.included_from {
+            write!(f, "\n   included from {}:{}", parent.name, line + 1)?;
+            file = parent;
+        }
+
+        Ok(())
     }
 }
 
-fn tokenize(source: String) -> Vec<Line> {
-    let lines: Vec<(usize, &str)> = source
-        .lines()
-        .filter(|l| !l.starts_with(';'))
-        .map(|l| l.trim())
-        .enumerate()
-        .collect();
-    let mut tokens = vec![];
+/// Deepest a macro can be expanded inside other macros, stops recursive macros
+const MAX_MACRO_DEPTH: usize = 64;
 
-    for line in lines {
-        let mut token_line = vec![];
+/// A `macro name params... endmacro` definition
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Line>,
+}
 
-        let mut x = 0;
+impl Macro {
+    fn new(params: Vec<String>) -> Self {
+        Self {
+            params,
+            body: vec![],
+        }
+    }
 
-        while x < line.1.len() {
-            if line.1.chars().nth(x).is_some_and(|c| c.is_whitespace()) {
-                x += 1;
-                continue;
+    /// Substitutes the arguments for the parameters and gives every `@label`
+    /// a name unique to this expansion
+    fn expand(&self, invocation: &Line, expansion: usize) -> Vec<Line> {
+        let (name, args) = (&invocation.0[0].value, &invocation.0[1..]);
+        let invoked_at = Rc::new(invocation.0[0].span.clone());
+        let mut lines = self.body.clone();
+
+        for line in &mut lines {
+            // Parameters only stand for operands, labels and the mnemonic are kept as written
+            let mnemonic = line.0.iter().position(|token| !token.value.ends_with(':'));
+            for (i, token) in line.0.iter_mut().enumerate() {
+                let operand = mnemonic.is_some_and(|m| i > m);
+                token.span.expanded_from = Some(invoked_at.clone());
+                token.value = expr::substitute(&token.value, |word| {
+                    match self.params.iter().position(|p| p == word) {
+                        Some(p) if operand => Some(args[p].value.clone()),
+                        _ => {
+                            let label = word.strip_prefix('@')?;
+                            Some(format!("__{}_{}_{}", name, expansion, label))
+                        }
+                    }
+                });
             }
-            token_line.push(token_from_line(line.1, &mut x, line.0 as u32));
-            x += 1; // Skip whitespace after token
         }
-        tokens.push(Line(token_line));
+
+        lines
+    }
+}
+
+/// Labels and function names: a letter or `_` followed by letters, digits or `_`
+fn is_valid_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && Register::try_from(name).is_err()
+}
+
+/// Joins the operands after the register operands into one expression, so
+/// `Imm A size * 2 + 1` works without parentheses. `.word` takes several values
+/// and `[reg ± offset]` has its own syntax, so those lines are kept as they are
+fn join_expression(line: &mut Line) {
+    if line.0[0].value == ".word" {
+        return;
+    }
+    let start = 1 + line.0[1..]
+        .iter()
+        .take_while(|token| Register::try_from(token.value.as_str()).is_ok())
+        .count();
+    let rest = &line.0[start.min(line.0.len())..];
+    if rest.len() < 2 || rest.iter().any(|token| token.value.contains('[')) {
+        return;
     }
 
-    tokens
+    // Separate values like `Imm A 1 2` are still too many arguments
+    let is_operator = |c: char| "+-*/%&|^~<>".contains(c);
+    let continues = rest
+        .windows(2)
+        .all(|pair| pair[0].value.ends_with(is_operator) || pair[1].value.starts_with(is_operator));
+    if continues {
+        let value: Vec<&str> = rest.iter().map(|token| token.value.as_str()).collect();
+        let value = value.join(" ");
+        let last = &rest[rest.len() - 1].span;
+        // Errors underline the whole expression when it was written on one line
+        let len = match Rc::ptr_eq(&last.file, &rest[0].span.file) && last.line == rest[0].span.line
+        {
+            true => last.column + last.len - rest[0].span.column,
+            false => rest[0].span.len,
+        };
+        line.0[start].value = value;
+        line.0[start].span.len = len;
+        line.0.truncate(start + 1);
+    }
 }
 
-fn assemble(file_name: String, source: String) -> Result<Vec<u32>, CompError> {
-    let tokens = tokenize(source);
+/// Amount of words a line assembles to, needed to place labels before encoding
+fn instruction_size(line: &Line) -> usize {
+    match line.0[0].value.as_str() {
+        // Li with a value known to fit in a single SImm or ImmLo only needs one word
+        "Li" => match line
+            .0
+            .get(2)
+            .map(|token| parse_li_literal(&token.value, &|_| None))
+        {
+            Some(Ok(Value {
+                offset: -4096..=0xffff,
+                ..
+            })) => 1,
+            _ => 2,
+        },
+        _ => 1,
+    }
+}
 
-    let mut buffer = vec![];
+/// The data section is copied into the VM's memory, so it can't be bigger than that
+const DATA_TOO_BIG: &str = "Data section doesn't fit in memory, see --mem";
 
-    let err_from_ordering = |ordering: Ordering, line: &Line, file: &str| match ordering {
-        Ordering::Less => Err(CompError(
+/// Amount of words a data directive puts in the data section
+fn data_size(line: &Line, memory_size: usize) -> Result<usize, CompError> {
+    match line.0[0].value.as_str() {
+        ".word" if line.0.len() > 1 => Ok(line.0.len() - 1),
+        ".string" if line.0.len() == 2 => match parse_string_literal(&line.0[1].value) {
+            Ok(words) => Ok(words.len()),
+            Err(msg) => Err(CompError(line.clone(), 1, msg)),
+        },
+        // The size has to be known before any label is, so only constants are allowed
+        ".zero" if line.0.len() == 2 => match expr::evaluate(&line.0[1].value, &|_| None) {
+            Ok(n) if (0..=memory_size as i64).contains(&n) => Ok(n as usize),
+            Ok(n) if n >= 0 => Err(CompError(line.clone(), 1, DATA_TOO_BIG)),
+            Ok(_) => Err(CompError(
+                line.clone(),
+                1,
+                "Negative literal not allowed here",
+            )),
+            Err(e) => Err(CompError(line.clone(), 1, e.message())),
+        },
+        ".word" | ".string" | ".zero" => Err(CompError(
             line.clone(),
             0,
-            "Not enough arguments provided",
-            file_name.clone(),
+            "Wrong amount of arguments for data directive",
         )),
-        Ordering::Equal => Ok(()),
-        Ordering::Greater => Err(CompError(
+        _ => Err(CompError(
             line.clone(),
             0,
-            "Too many arguments provided",
-            file.to_owned(),
+            "Expected a data directive (.word, .string or .zero)",
         )),
+    }
+}
+
+/// Decodes a `"..."` literal into one word per character, with the
+/// `\n`, `\t`, `\r`, `\0`, `\\` and `\"` escapes
+fn parse_string_literal(value: &str) -> Result<Vec<u32>, &'static str> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|_| value.len() >= 2)
+        .ok_or("Expected a quoted string")?;
+
+    let mut words = vec![];
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                _ => return Err("Unknown escape sequence"),
+            },
+            '"' => return Err("Unescaped quote inside a string"),
+            c => c,
+        };
+        words.push(c as u32);
+    }
+
+    Ok(words)
+}
+
+/// Li takes integer expressions and floats, which are loaded as their IEEE-754 bits.
+/// Floats are told apart by their decimal point: `1.0`, `-0.5`, `6.02e23`
+fn parse_li_literal(
+    value: &str,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<Value, ExprError> {
+    if value.contains('.') {
+        if let Ok(float) = value.parse::<f32>() {
+            return Ok(Value::constant(float.to_bits() as i64));
+        }
+    }
+
+    expr::evaluate_relocatable(value, lookup)
+}
+
+/// Parses the bracketed address of Load and Store, with the whitespace already removed
+fn parse_memory_operand(
+    operand: &str,
+    lookup: &dyn Fn(&str) -> Option<Value>,
+) -> Result<(Register, Value), &'static str> {
+    let inner = operand
+        .strip_prefix('[')
+        .and_then(|o| o.strip_suffix(']'))
+        .ok_or("Expected a memory operand like [FP - 2]")?;
+
+    let (reg, offset) = match inner.find(['+', '-']) {
+        Some(i) => (&inner[..i], &inner[i..]),
+        None => (inner, "0"),
     };
+    let reg = Register::try_from(reg).map_err(|_| "Invalid register name")?;
+    let offset = expr::evaluate_relocatable(offset, lookup).map_err(ExprError::message)?;
 
-    let get_reg_or_ret = |idx: usize, line: &Line, file: &str| -> Result<Register, CompError> {
-        Register::try_from(line.0[idx].value.as_str()).map_err(|_| {
-            CompError(
-                line.clone(),
-                idx as u32,
-                "Invalid register name",
-                file.to_string(),
-            )
-        })
+    Ok((reg, offset))
+}
+
+fn literal_error_message(error: InvalidLiteralError, signed: bool) -> &'static str {
+    match (error, signed) {
+        (InvalidLiteralError::InvalidDigit, _) => "Invalid number literal",
+        (InvalidLiteralError::Negative, _) => "Negative literal not allowed here",
+        (InvalidLiteralError::TooBig, false) => "Literal doesn't fit in 13 bits (0..=8191)",
+        (InvalidLiteralError::TooBig, true) => {
+            "Literal doesn't fit in 13 signed bits (-4096..=4095)"
+        }
+    }
+}
+
+/// Finds an included file next to the file including it, then in the include paths
+fn resolve_include(name: &str, from: &SourceFile, include_paths: &[PathBuf]) -> Option<PathBuf> {
+    let own_dir = Path::new(&from.name).parent().map(Path::to_path_buf);
+    own_dir
+        .into_iter()
+        .chain(include_paths.iter().cloned())
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+fn assemble(
+    file_name: String,
+    source: String,
+    include_paths: &[PathBuf],
+    memory_size: usize,
+) -> Result<Program, Diagnostics> {
+    let root = SourceFile::new(file_name, source);
+    let tokens = tokenize(&root);
+    let mut diagnostics = Diagnostics::default();
+
+    let err_from_ordering = |ordering: Ordering, line: &Line| match ordering {
+        Ordering::Less => Err(CompError(line.clone(), 0, "Not enough arguments provided")),
+        Ordering::Equal => Ok(()),
+        Ordering::Greater => Err(CompError(line.clone(), 0, "Too many arguments provided")),
+    };
+
+    let get_reg_or_ret = |idx: usize, line: &Line| -> Result<Register, CompError> {
+        Register::try_from(line.0[idx].value.as_str())
+            .map_err(|_| CompError(line.clone(), idx as u32, "Invalid register name"))
     };
 
     let mut definitions: HashMap<String, String> = HashMap::new();
-    let mut functions: HashMap<String, usize> = HashMap::new();
+    let mut symbols: HashMap<String, (SectionKind, usize)> = HashMap::new();
+    // `.global` and `.extern` names, checked once every label is known
+    let mut exports: Vec<Token> = vec![];
+    let mut imports: Vec<Token> = vec![];
+    let mut instructions: Vec<Line> = vec![];
+    let mut address = 0;
+    let mut listing = Listing::default();
 
-    for (i, line) in tokens.iter().enumerate() {
-        let mut line = line.clone();
+    // First pass: expand definitions and macros, move function bodies after the top level code
+    let mut top_level: Vec<Line> = vec![];
+    let mut functions: Vec<Line> = vec![];
+    let mut current_function: Option<Line> = None;
+    let mut data: Vec<Line> = vec![];
+    let mut in_data = false;
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut current_macro: Option<(Line, Macro)> = None;
+    let mut expansions = 0;
+    // Lines left to process (in reverse), with how deeply nested in macro expansions they are
+    let mut pending: Vec<(Line, usize)> = tokens.into_iter().rev().map(|l| (l, 0)).collect();
+    let mut first_pass = |mut line: Line,
+                          depth: usize,
+                          pending: &mut Vec<(Line, usize)>,
+                          diagnostics: &mut Diagnostics|
+     -> Result<(), CompError> {
         if line.0.is_empty() {
-            continue;
+            return Ok(());
         }
 
-        // Definition
-        if line.0[0].value.as_str() == "%" {
-            definitions.insert(line.0[1].value.clone(), line.0[2].value.clone());
-            continue;
+        // Macro bodies are kept as written and only expanded when used
+        if let Some((_, definition)) = &mut current_macro {
+            match line.0[0].value.as_str() {
+                "endmacro" => {
+                    err_from_ordering(line.0.len().cmp(&1), &line)?;
+                    let (header, definition) = current_macro.take().unwrap();
+                    if macros
+                        .insert(header.0[1].value.clone(), definition)
+                        .is_some()
+                    {
+                        return Err(CompError(header, 1, "Macro redefined"));
+                    }
+                }
+                "macro" => return Err(CompError(line, 0, "Nested macro definition")),
+                _ => definition.body.push(line),
+            }
+            return Ok(());
         }
 
-        for token in &mut line.0 {
-            if definitions.contains_key(&token.value) {
-                token
+        match line.0[0].value.as_str() {
+            // Definition
+            "%" => {
+                if line.0.len() < 3 {
+                    err_from_ordering(Ordering::Less, &line)?;
+                }
+                // Expanded right away, so definitions can use the ones before them
+                let value: Vec<&str> = line.0[2..].iter().map(|t| t.value.as_str()).collect();
+                let value =
+                    expr::substitute(&value.join(" "), |name| definitions.get(name).cloned());
+                if definitions.insert(line.0[1].value.clone(), value).is_some() {
+                    diagnostics.warning(CompError(line, 1, "Definition overrides an earlier one"));
+                }
+                return Ok(());
+            }
+            "macro" => {
+                if line.0.len() < 2 {
+                    err_from_ordering(Ordering::Less, &line)?;
+                }
+                for (i, token) in line.0.iter().enumerate().skip(1) {
+                    if !is_valid_symbol(&token.value) {
+                        let msg = match i {
+                            1 => "Invalid macro name",
+                            _ => "Invalid macro parameter name",
+                        };
+                        return Err(CompError(line, i as u32, msg));
+                    }
+                }
+                let params = line.0[2..].iter().map(|t| t.value.clone()).collect();
+                current_macro = Some((line, Macro::new(params)));
+                return Ok(());
+            }
+            "endmacro" => {
+                return Err(CompError(line, 0, "endmacro outside of a macro"));
+            }
+            "include" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let name = match line.0[1]
                     .value
-                    .clone_from(definitions.get(&token.value).unwrap());
+                    .strip_prefix('"')
+                    .and_then(|n| n.strip_suffix('"'))
+                {
+                    Some(name) if !name.is_empty() => name,
+                    _ => return Err(CompError(line, 1, "Expected a quoted file name")),
+                };
+                let from = &line.0[0].span.file;
+                let path = match resolve_include(name, from, include_paths) {
+                    Some(path) => path,
+                    None => return Err(CompError(line, 1, "Included file not found")),
+                };
+                if from.in_include_chain(&path) {
+                    return Err(CompError(line, 1, "File includes itself"));
+                }
+                let source = match std::fs::read_to_string(&path) {
+                    Ok(source) => source,
+                    Err(_) => return Err(CompError(line, 1, "Couldn't read the included file")),
+                };
+
+                let name = path.to_string_lossy().into_owned();
+                let file = SourceFile::included(name, source, &line.0[0].span);
+                let included = tokenize(&file);
+                pending.extend(included.into_iter().rev().map(|l| (l, depth)));
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        for token in &mut line.0 {
+            token.value = expr::substitute(&token.value, |name| definitions.get(name).cloned());
+        }
+
+        // A label in front of a macro goes on its own line, so it marks the first expanded line
+        if line.0.len() > 1
+            && line.0[0].value.ends_with(':')
+            && macros.contains_key(&line.0[1].value)
+        {
+            let invocation = Line(line.0.split_off(1));
+            pending.push((invocation, depth));
+            pending.push((line, depth));
+            return Ok(());
+        }
+
+        if let Some(definition) = macros.get(&line.0[0].value) {
+            if depth >= MAX_MACRO_DEPTH {
+                return Err(CompError(line, 0, "Macro expansion too deep"));
+            }
+            let arity = definition.params.len() + 1;
+            err_from_ordering(line.0.len().cmp(&arity), &line)?;
+
+            expansions += 1;
+            let expanded = definition.expand(&line, expansions);
+            pending.extend(expanded.into_iter().rev().map(|l| (l, depth + 1)));
+            return Ok(());
+        }
+
+        match line.0[0].value.as_str() {
+            ".data" | ".text" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                if current_function.is_some() {
+                    return Err(CompError(line, 0, "Section change inside a function"));
+                }
+                in_data = line.0[0].value == ".data";
             }
+            ".global" | ".extern" => {
+                if line.0.len() < 2 {
+                    err_from_ordering(Ordering::Less, &line)?;
+                }
+                for (i, token) in line.0.iter().enumerate().skip(1) {
+                    if !is_valid_symbol(&token.value) {
+                        return Err(CompError(line, i as u32, "Invalid symbol name"));
+                    }
+                }
+                match line.0[0].value.as_str() {
+                    ".global" => exports.extend(line.0.into_iter().skip(1)),
+                    _ => imports.extend(line.0.into_iter().skip(1)),
+                }
+            }
+            _ if in_data => data.push(line),
+            "Fn" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                if current_function.is_some() {
+                    return Err(CompError(line, 0, "Nested function definition"));
+                }
+                if !is_valid_symbol(&line.0[1].value) {
+                    return Err(CompError(line, 1, "Invalid function name"));
+                }
+                // The function name becomes a label on the first instruction of the body
+                let mut name = line.0[1].clone();
+                listing.functions.push(name.value.clone());
+                name.value.push(':');
+                functions.push(Line(vec![name]));
+                current_function = Some(line);
+            }
+            "EndFn" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                if current_function.take().is_none() {
+                    return Err(CompError(line, 0, "EndFn outside of a function"));
+                }
+                // Implicit return, like the implicit exit at the end of the program
+                line.0[0].value = "Ret".to_string();
+                functions.push(line);
+            }
+            _ if current_function.is_some() => functions.push(line),
+            _ => top_level.push(line),
+        }
+
+        Ok(())
+    };
+    while let Some((line, depth)) = pending.pop() {
+        if let Err(e) = first_pass(line, depth, &mut pending, &mut diagnostics) {
+            diagnostics.error(e);
         }
+    }
+    if let Some(line) = current_function {
+        diagnostics.error(CompError(
+            line,
+            0,
+            "Function without an EndFn, functions don't end at their Ret anymore",
+        ));
+    }
+    if let Some((line, _)) = current_macro {
+        diagnostics.error(CompError(line, 0, "Macro without an endmacro"));
+    }
+
+    // Implicit exit syscall between the top level code and the functions.
+    // Files with nothing to run, like libraries, don't get one
+    let entry = (!top_level.is_empty()).then_some(0);
+    let exit_source = "Imm A 0\nImm B 0\nSyscall".to_string();
+    let exit = match entry {
+        Some(_) => tokenize(&SourceFile::new("<implicit exit>".to_string(), exit_source)),
+        None => vec![],
+    };
+
+    let mut defines: Vec<_> = definitions.into_iter().collect();
+    defines.sort();
+    listing.defines = defines;
+
+    // Second pass: assign an address to every label, data labels get their address in memory
+    let code = top_level.into_iter().chain(exit).chain(functions);
+    let mut data_lines: Vec<Line> = vec![];
+    // Rows of the listing the lines will fill once they are encoded
+    let mut instruction_rows = vec![];
+    let mut data_rows = vec![];
+    let mut data_address = 0;
+    for (mut line, is_data) in code
+        .map(|l| (l, false))
+        .chain(data.into_iter().map(|l| (l, true)))
+    {
+        let address = if is_data {
+            &mut data_address
+        } else {
+            &mut address
+        };
+        let section = match is_data {
+            true => SectionKind::Data,
+            false => SectionKind::Code,
+        };
+        let row = ListingLine {
+            section,
+            address: *address,
+            words: vec![],
+            span: line.0[0].span.clone(),
+            text: line
+                .0
+                .iter()
+                .map(|t| t.value.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        };
+
+        // Label, optionally followed by an instruction on the same line
+        if let Some(name) = line.0[0].value.strip_suffix(':') {
+            if !is_valid_symbol(name) {
+                diagnostics.error(CompError(line, 0, "Invalid label name"));
+                continue;
+            }
+            if symbols
+                .insert(name.to_owned(), (section, *address))
+                .is_some()
+            {
+                diagnostics.error(CompError(line, 0, "Label redefined"));
+                continue;
+            }
+            line.0.remove(0);
+            if line.0.is_empty() {
+                listing.lines.push(row);
+                continue;
+            }
+        }
+
+        join_expression(&mut line);
+        if is_data {
+            match data_size(&line, memory_size) {
+                Ok(size) if *address + size > memory_size => {
+                    diagnostics.error(CompError(line, 0, DATA_TOO_BIG));
+                    continue;
+                }
+                Ok(size) => *address += size,
+                Err(e) => {
+                    diagnostics.error(e);
+                    continue;
+                }
+            }
+            data_rows.push(listing.lines.len());
+            data_lines.push(line);
+        } else {
+            *address += instruction_size(&line);
+            instruction_rows.push(listing.lines.len());
+            instructions.push(line);
+        }
+        listing.lines.push(row);
+    }
 
+    for import in &imports {
+        if symbols.contains_key(&import.value) {
+            let line = Line(vec![import.clone()]);
+            diagnostics.error(CompError(
+                line,
+                0,
+                "Imported symbol is defined in this file",
+            ));
+        }
+    }
+
+    // Labels are relative to the start of their section, imports to wherever they end up
+    let lookup = |name: &str| match symbols.get(name) {
+        Some((section, addr)) => Some(Value {
+            offset: *addr as i64,
+            base: Some(Base::Section(*section)),
+        }),
+        None => imports.iter().any(|i| i.value == name).then(|| Value {
+            offset: 0,
+            base: Some(Base::Import(name.to_string())),
+        }),
+    };
+
+    // Fields that depend on a label, with the word of the line they are in
+    let pending_relocations: RefCell<Vec<(usize, RelocationKind, Value)>> = RefCell::new(vec![]);
+    let relocate = |word: usize, kind: RelocationKind, value: &Value| {
+        if value.base.is_some() {
+            pending_relocations
+                .borrow_mut()
+                .push((word, kind, value.clone()));
+        }
+    };
+
+    let get_value_or_ret = |idx: usize, line: &Line| -> Result<Value, CompError> {
+        expr::evaluate_relocatable(&line.0[idx].value, &lookup)
+            .map_err(|e| CompError(line.clone(), idx as u32, e.message()))
+    };
+
+    // Unsigned literals zero extend, signed ones (offsets, SImm) sign extend
+    let get_lit_or_ret =
+        |idx: usize, kind: RelocationKind, line: &Line| -> Result<Bit13Literal, CompError> {
+            let value = get_value_or_ret(idx, line)?;
+            let signed = kind != RelocationKind::Lit13;
+            let lit = match signed {
+                true => Bit13Literal::from_signed(value.offset),
+                false => Bit13Literal::from_unsigned(value.offset),
+            };
+            relocate(0, kind, &value);
+            lit.map_err(|e| CompError(line.clone(), idx as u32, literal_error_message(e, signed)))
+        };
+
+    // Jump targets and other addresses, labels included
+    let get_addr_or_ret = |idx: usize, line: &Line| -> Result<Bit13Literal, CompError> {
+        get_lit_or_ret(idx, RelocationKind::Lit13, line)
+    };
+
+    // Third pass: encode the instructions with every label known
+    let encode = |line: Line, buffer: &mut Vec<u32>| -> Result<(), CompError> {
         match line.0[0].value.split_whitespace().collect::<Vec<_>>()[0] {
             "Add" => {
-                err_from_ordering(line.0.len().cmp(&4), &line, &file_name)?;
-                let r1 = get_reg_or_ret(1, &line, &file_name)?;
-                let r2 = get_reg_or_ret(2, &line, &file_name)?;
-                let r3 = get_reg_or_ret(3, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
 
                 buffer.push(Opcode::Add(r1, r2, r3).into())
             }
             "Sub" => {
-                err_from_ordering(line.0.len().cmp(&4), &line, &file_name)?;
-                let r1 = get_reg_or_ret(1, &line, &file_name)?;
-                let r2 = get_reg_or_ret(2, &line, &file_name)?;
-                let r3 = get_reg_or_ret(3, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
 
                 buffer.push(Opcode::Sub(r1, r2, r3).into())
             }
             "Mul" => {
-                err_from_ordering(line.0.len().cmp(&4), &line, &file_name)?;
-                let r1 = get_reg_or_ret(1, &line, &file_name)?;
-                let r2 = get_reg_or_ret(2, &line, &file_name)?;
-                let r3 = get_reg_or_ret(3, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
 
                 buffer.push(Opcode::Mul(r1, r2, r3).into())
             }
             "Div" => {
-                err_from_ordering(line.0.len().cmp(&4), &line, &file_name)?;
-                let r1 = get_reg_or_ret(1, &line, &file_name)?;
-                let r2 = get_reg_or_ret(2, &line, &file_name)?;
-                let r3 = get_reg_or_ret(3, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
 
                 buffer.push(Opcode::Div(r1, r2, r3).into())
             }
+            "IMul" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::IMul(r1, r2, r3).into())
+            }
+            "IDiv" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::IDiv(r1, r2, r3).into())
+            }
+            "FAdd" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::FAdd(r1, r2, r3).into())
+            }
+            "FSub" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::FSub(r1, r2, r3).into())
+            }
+            "FMul" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::FMul(r1, r2, r3).into())
+            }
+            "FDiv" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::FDiv(r1, r2, r3).into())
+            }
+            "FCmp" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+
+                buffer.push(Opcode::FCmp(r1, r2).into())
+            }
+            "ItoF" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+
+                buffer.push(Opcode::ItoF(r1, r2).into())
+            }
+            "FtoI" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+
+                buffer.push(Opcode::FtoI(r1, r2).into())
+            }
             "Imm" => {
-                err_from_ordering(line.0.len().cmp(&3), &line, &file_name)?;
-                let register = get_reg_or_ret(1, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let register = get_reg_or_ret(1, &line)?;
 
-                let imm_value = match Bit13Literal::try_from(line.0[2].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                // Labels give the address of code or data
+                let imm_value = get_addr_or_ret(2, &line)?;
 
                 buffer.push(Opcode::Imm(register, imm_value).into())
             }
+            "SImm" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let register = get_reg_or_ret(1, &line)?;
+                let imm_value = get_lit_or_ret(2, RelocationKind::SignedLit13, &line)?;
+
+                buffer.push(Opcode::SImm(register, imm_value).into())
+            }
+            "ImmLo" | "ImmHi" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let register = get_reg_or_ret(1, &line)?;
+
+                let value = get_value_or_ret(2, &line)?;
+                relocate(0, RelocationKind::Lit16, &value);
+                let imm_value = match value.offset {
+                    v @ 0..=0xffff => Bit16Literal(v as u16),
+                    _ => {
+                        return Err(CompError(
+                            line,
+                            2,
+                            "Literal doesn't fit in 16 bits (0..=65535)",
+                        ))
+                    }
+                };
+
+                if line.0[0].value == "ImmLo" {
+                    buffer.push(Opcode::ImmLo(register, imm_value).into())
+                } else {
+                    buffer.push(Opcode::ImmHi(register, imm_value).into())
+                }
+            }
+            "Li" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let register = get_reg_or_ret(1, &line)?;
+
+                let value = match parse_li_literal(&line.0[2].value, &lookup) {
+                    Ok(v) if (i32::MIN as i64..=u32::MAX as i64).contains(&v.offset) => v,
+                    Ok(_) => return Err(CompError(line, 2, "Literal doesn't fit in 32 bits")),
+                    Err(e) => return Err(CompError(line, 2, e.message())),
+                };
+                relocate(0, RelocationKind::Lo16, &value);
+                relocate(1, RelocationKind::Hi16, &value);
+                let value = value.offset;
+
+                // The size was picked without knowing the labels, so it decides the encoding.
+                // Labels always make it two words
+                if instruction_size(&line) == 1 {
+                    // Small negative values are a single sign extended SImm
+                    if value < 0 {
+                        let lit = Bit13Literal::from_signed(value).unwrap();
+                        buffer.push(Opcode::SImm(register, lit).into());
+                    } else {
+                        buffer.push(Opcode::ImmLo(register, Bit16Literal(value as u16)).into());
+                    }
+                    return Ok(());
+                }
+
+                let value = value as u32;
+                let lo = Bit16Literal((value & 0xffff) as u16);
+                let hi = Bit16Literal((value >> 16) as u16);
+                buffer.push(Opcode::ImmLo(register, lo).into());
+                buffer.push(Opcode::ImmHi(register, hi).into());
+            }
             "Push" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let register = get_reg_or_ret(1, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let register = get_reg_or_ret(1, &line)?;
 
                 buffer.push(Opcode::Push(register).into())
             }
             "PushImm" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 1, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let imm_value = get_addr_or_ret(1, &line)?;
 
                 buffer.push(Opcode::PushImm(imm_value).into())
             }
             "Pop" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let register = get_reg_or_ret(1, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let register = get_reg_or_ret(1, &line)?;
 
                 buffer.push(Opcode::Pop(register).into())
             }
             "Cmp" => {
-                err_from_ordering(line.0.len().cmp(&3), &line, &file_name)?;
-                let r1 = get_reg_or_ret(1, &line, &file_name)?;
-                let r2 = get_reg_or_ret(2, &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
 
                 buffer.push(Opcode::Cmp(r1, r2).into())
             }
+            "SCmp" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+
+                buffer.push(Opcode::SCmp(r1, r2).into())
+            }
             "Jmp" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Jmp(imm_value).into())
+                buffer.push(Opcode::Jmp(addr).into())
             }
             "Je" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Je(imm_value).into())
+                buffer.push(Opcode::Je(addr).into())
             }
             "Jne" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Jne(imm_value).into())
+                buffer.push(Opcode::Jne(addr).into())
             }
             "Jg" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Jg(imm_value).into())
+                buffer.push(Opcode::Jg(addr).into())
             }
             "Jge" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Jge(imm_value).into())
+                buffer.push(Opcode::Jge(addr).into())
             }
             "Jz" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Jz(imm_value).into())
+                buffer.push(Opcode::Jz(addr).into())
             }
             "Jnz" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let imm_value = match Bit13Literal::try_from(line.0[1].value.as_str()) {
-                    Ok(v) => v,
-                    Err(_) => return Err(CompError(line, 2, "Invalid number literal", file_name)),
-                };
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jnz(addr).into())
+            }
+            "Jl" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jl(addr).into())
+            }
+            "Jle" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jle(addr).into())
+            }
+            "Jc" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jc(addr).into())
+            }
+            "Jnc" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jnc(addr).into())
+            }
+            "Jo" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jo(addr).into())
+            }
+            "Jno" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Jno(addr).into())
+            }
+            "Js" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Js(addr).into())
+            }
+            "Jns" => {
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
 
-                buffer.push(Opcode::Jnz(imm_value).into())
+                buffer.push(Opcode::Jns(addr).into())
             }
             "Ret" => {
-                err_from_ordering(line.0.len().cmp(&1), &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
                 buffer.push(Opcode::Ret.into())
             }
             "Call" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                let addr = functions.get(&line.0[1].value).unwrap();
-                let b13 = Bit13Literal(*addr as u16);
-                buffer.push(Opcode::Call(b13).into())
-            }
-            "Fn" => {
-                err_from_ordering(line.0.len().cmp(&2), &line, &file_name)?;
-                functions.insert(line.0[1].value.clone(), i);
-                buffer.push(Opcode::Fn.into())
+                err_from_ordering(line.0.len().cmp(&2), &line)?;
+                let addr = get_addr_or_ret(1, &line)?;
+
+                buffer.push(Opcode::Call(addr).into())
             }
             "StackAdd" => {
-                err_from_ordering(line.0.len().cmp(&1), &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
                 buffer.push(Opcode::StackAdd.into())
             }
             "StackSub" => {
-                err_from_ordering(line.0.len().cmp(&1), &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
                 buffer.push(Opcode::StackSub.into())
             }
             "StackMul" => {
-                err_from_ordering(line.0.len().cmp(&1), &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
                 buffer.push(Opcode::StackMul.into())
             }
             "StackDiv" => {
-                err_from_ordering(line.0.len().cmp(&1), &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
                 buffer.push(Opcode::StackDiv.into())
             }
+            "Load" | "Store" => {
+                if line.0.len() < 3 {
+                    err_from_ordering(Ordering::Less, &line)?;
+                }
+                let r1 = get_reg_or_ret(1, &line)?;
+                let (r2, offset) = if line.0[2].value.starts_with('[') {
+                    // `[reg]`, `[reg + offset]` or `[reg - offset]`, spread over any amount of tokens
+                    let operand: String = line.0[2..].iter().map(|t| t.value.as_str()).collect();
+                    let (r2, offset) = match parse_memory_operand(&operand, &lookup) {
+                        Ok(v) => v,
+                        Err(msg) => return Err(CompError(line, 2, msg)),
+                    };
+                    relocate(0, RelocationKind::Offset13, &offset);
+                    match Bit13Literal::from_signed(offset.offset) {
+                        Ok(offset) => (r2, offset),
+                        Err(e) => return Err(CompError(line, 2, literal_error_message(e, true))),
+                    }
+                } else {
+                    if line.0.len() != 3 {
+                        err_from_ordering(line.0.len().cmp(&4), &line)?;
+                    }
+                    let r2 = get_reg_or_ret(2, &line)?;
+                    let offset = match line.0.get(3) {
+                        Some(_) => get_lit_or_ret(3, RelocationKind::Offset13, &line)?,
+                        None => Bit13Literal(0),
+                    };
+                    (r2, offset)
+                };
+
+                if line.0[0].value == "Load" {
+                    buffer.push(Opcode::Load(r1, r2, offset).into())
+                } else {
+                    buffer.push(Opcode::Store(r1, r2, offset).into())
+                }
+            }
+            "And" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::And(r1, r2, r3).into())
+            }
+            "Or" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::Or(r1, r2, r3).into())
+            }
+            "Xor" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::Xor(r1, r2, r3).into())
+            }
+            "Shl" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::Shl(r1, r2, r3).into())
+            }
+            "Shr" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::Shr(r1, r2, r3).into())
+            }
+            "Sar" => {
+                err_from_ordering(line.0.len().cmp(&4), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+                let r3 = get_reg_or_ret(3, &line)?;
+
+                buffer.push(Opcode::Sar(r1, r2, r3).into())
+            }
+            "Not" => {
+                err_from_ordering(line.0.len().cmp(&3), &line)?;
+                let r1 = get_reg_or_ret(1, &line)?;
+                let r2 = get_reg_or_ret(2, &line)?;
+
+                buffer.push(Opcode::Not(r1, r2).into())
+            }
+            "StackAnd" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackAnd.into())
+            }
+            "StackOr" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackOr.into())
+            }
+            "StackXor" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackXor.into())
+            }
+            "StackNot" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackNot.into())
+            }
+            "StackShl" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackShl.into())
+            }
+            "StackShr" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackShr.into())
+            }
+            "StackSar" => {
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
+                buffer.push(Opcode::StackSar.into())
+            }
             "Syscall" => {
-                err_from_ordering(line.0.len().cmp(&1), &line, &file_name)?;
+                err_from_ordering(line.0.len().cmp(&1), &line)?;
                 buffer.push(Opcode::Syscall.into())
             }
-            _ => return Err(CompError(line, 0, "Unknown instruction", file_name)),
+            _ => return Err(CompError(line, 0, "Unknown instruction")),
+        }
+
+        Ok(())
+    };
+    let mut buffer = vec![];
+    let mut relocations = vec![];
+    for (line, row) in instructions.into_iter().zip(instruction_rows) {
+        let start = buffer.len();
+        if let Err(e) = encode(line, &mut buffer) {
+            diagnostics.error(e);
+        }
+        for (word, kind, value) in pending_relocations.take() {
+            relocations.push((SectionKind::Code, start + word, kind, value));
+        }
+        listing.lines[row].words = buffer[start..].to_vec();
+    }
+
+    let mut data = vec![];
+    for (line, row) in data_lines.into_iter().zip(data_rows) {
+        let start = data.len();
+        match line.0[0].value.as_str() {
+            ".word" => {
+                for (i, token) in line.0.iter().enumerate().skip(1) {
+                    let value = match parse_li_literal(&token.value, &lookup) {
+                        Ok(v) if (i32::MIN as i64..=u32::MAX as i64).contains(&v.offset) => {
+                            if v.base.is_some() {
+                                let kind = RelocationKind::Word;
+                                relocations.push((SectionKind::Data, data.len(), kind, v.clone()));
+                            }
+                            v.offset as u32
+                        }
+                        Ok(_) => {
+                            let msg = "Literal doesn't fit in 32 bits";
+                            diagnostics.error(CompError(line.clone(), i as u32, msg));
+                            0
+                        }
+                        Err(e) => {
+                            diagnostics.error(CompError(line.clone(), i as u32, e.message()));
+                            0
+                        }
+                    };
+                    data.push(value);
+                }
+            }
+            ".string" => match parse_string_literal(&line.0[1].value) {
+                Ok(words) => data.extend(words),
+                Err(msg) => diagnostics.error(CompError(line.clone(), 1, msg)),
+            },
+            _ => match data_size(&line, memory_size) {
+                Ok(size) => data.resize(data.len() + size, 0),
+                Err(e) => diagnostics.error(e),
+            },
+        }
+        listing.lines[row].words = data[start..].to_vec();
+    }
+
+    // Symbol table: every label, with the `.global` ones exported, then the imports
+    let mut labels: Vec<_> = symbols.iter().collect();
+    labels.sort_by_key(|(name, (section, addr))| (*section as u32, *addr, *name));
+    let mut table: Vec<Symbol> = labels
+        .into_iter()
+        .map(|(name, (section, addr))| Symbol {
+            name: name.clone(),
+            binding: match exports.iter().any(|e| e.value == *name) {
+                true => Binding::Export,
+                false => Binding::Local,
+            },
+            section: Some(*section),
+            value: *addr as u32,
+        })
+        .collect();
+    for export in &exports {
+        if !symbols.contains_key(&export.value) {
+            let line = Line(vec![export.clone()]);
+            diagnostics.error(CompError(line, 0, "Exported symbol is never defined"));
+        }
+    }
+    for import in &imports {
+        if !table.iter().any(|s| s.name == import.value) {
+            table.push(Symbol {
+                name: import.value.clone(),
+                binding: Binding::Import,
+                section: None,
+                value: 0,
+            });
         }
     }
-    // Exit with 0 exit code
-    buffer.push(Opcode::Imm(Register::A, Bit13Literal::try_from("0").unwrap()).into());
-    buffer.push(Opcode::Imm(Register::B, Bit13Literal::try_from("0").unwrap()).into());
-    buffer.push(Opcode::Syscall.into());
 
-    Ok(buffer)
+    let relocations = relocations
+        .into_iter()
+        .map(|(section, offset, kind, value)| Relocation {
+            section,
+            offset: offset as u32,
+            kind,
+            target: match value.base.unwrap() {
+                Base::Section(section) => RelocationTarget::Section(section),
+                Base::Import(name) => {
+                    let index = table.iter().position(|s| s.name == name).unwrap();
+                    RelocationTarget::Symbol(index as u32)
+                }
+            },
+            addend: value.offset as i32,
+        })
+        .collect();
+
+    if diagnostics.has_errors() {
+        return Err(diagnostics);
+    }
+    Ok(Program {
+        code: buffer,
+        data,
+        warnings: diagnostics.0,
+        entry,
+        symbols: table,
+        relocations,
+        listing,
+    })
 }
 
-fn write_binary_to_file(bin: Vec<u32>, file: String) -> Result<(), std::io::Error> {
-    let program: String = bin
+fn write_binary_to_file(
+    program: Program,
+    file: String,
+    legacy_hex: bool,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(import) = program
+        .symbols
         .iter()
-        .map(|num| format!("{:08x}", num)) // Format as hex, zero-padded to 8 characters
-        .map(|hex_str| hex_str.chars().rev().collect::<String>()) // Reverse the hex string
-        .collect::<Vec<String>>()
-        .join(" ");
-
+        .find(|s| s.binding == Binding::Import)
+    {
+        return Err(format!(
+            "`{}` is imported, assemble with --object and link with crlink",
+            import.name
+        )
+        .into());
+    }
+    let mut executable = Executable::new(program.code);
+    executable.entry = program
+        .entry
+        .ok_or("Nothing to run, the file has no top level code")?;
+    if !program.data.is_empty() {
+        if legacy_hex {
+            return Err("The legacy hex format can't hold a data section".into());
+        }
+        executable.sections.push(Section {
+            kind: SectionKind::Data,
+            address: 0,
+            data: program.data,
+        });
+    }
     let mut file = File::create(file)?;
-    file.write_all(program.as_bytes())?;
+
+    if legacy_hex {
+        file.write_all(executable.to_legacy_hex().as_bytes())?;
+    } else {
+        file.write_all(&executable.to_bytes())?;
+    }
 
     Ok(())
 }
 
-fn dissasemble_to_file(input_file: String, output: String) -> Result<(), Box<dyn Error>> {
-    let program = std::fs::read_to_string(input_file)?;
+fn dissasemble_to_file(
+    input_file: String,
+    output: String,
+    legacy_hex: bool,
+) -> Result<(), Box<dyn Error>> {
+    let executable = if legacy_hex {
+        Executable::from_legacy_hex(&std::fs::read_to_string(input_file)?)?
+    } else {
+        Executable::from_bytes(&std::fs::read(input_file)?)?
+    };
 
     let mut instructions: Vec<Opcode> = vec![];
 
-    for line in program.split_whitespace() {
-        let mut reversed = String::with_capacity(line.len());
-
-        for ch in line.chars().rev() {
-            reversed.push(ch);
+    if let Some(code) = executable.code() {
+        for (i, word) in code.data.iter().enumerate() {
+            match Opcode::try_from(*word) {
+                Ok(ins) => instructions.push(ins),
+                Err(e) => {
+                    return Err(format!("At address {}: {}", code.address as usize + i, e).into())
+                }
+            }
         }
-
-        instructions.push(Opcode::from(u32::from_str_radix(&reversed, 16)?));
     }
 
     let mut output = std::fs::File::create(output)?;
@@ -389,24 +1436,55 @@ fn dissasemble_to_file(input_file: String, output: String) -> Result<(), Box<dyn
         output.write_all(format!("{}\n", ins).as_bytes())?;
     }
 
+    for section in executable.data() {
+        output.write_all(b".data\n")?;
+        for word in &section.data {
+            output.write_all(format!(".word {}\n", word).as_bytes())?;
+        }
+    }
+
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
-    let source = std::fs::read_to_string(&args.input_file)?;
 
     if args.dissasemble {
-        dissasemble_to_file(args.input_file, args.output_file)?;
+        dissasemble_to_file(args.input_file, args.output_file, args.legacy_hex)?;
     } else {
-        let program = match assemble(args.input_file, source) {
+        let source = std::fs::read_to_string(&args.input_file)?;
+        let program = match assemble(
+            args.input_file,
+            source,
+            &args.include_paths,
+            args.memory_size,
+        ) {
             Ok(prog) => prog,
-            Err(e) => {
-                eprintln!("{}", e);
-                return Ok(());
+            Err(diagnostics) => {
+                eprintln!("{}\n", diagnostics);
+                eprintln!(
+                    "Assembly failed: {} error(s), {} warning(s)",
+                    diagnostics.count(Severity::Error),
+                    diagnostics.count(Severity::Warning)
+                );
+                std::process::exit(1);
             }
         };
-        write_binary_to_file(program, args.output_file)?;
+        for warning in &program.warnings {
+            eprintln!("{}\n", warning);
+        }
+        if let Some(file) = &args.listing {
+            std::fs::write(file, program.listing.render(&program.symbols))?;
+        }
+        if args.debug_info {
+            let info = program.listing.debug_info(&program.symbols);
+            std::fs::write(sidecar_path(&args.output_file), info.to_bytes()?)?;
+        }
+        if args.object {
+            std::fs::write(args.output_file, program.to_object().to_bytes()?)?;
+        } else {
+            write_binary_to_file(program, args.output_file, args.legacy_hex)?;
+        }
     }
 
     Ok(())
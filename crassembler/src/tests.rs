@@ -0,0 +1,744 @@
+#[cfg(test)]
+#[test]
+fn forward_label() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let source = "Jmp end\nImm A 1\nend:\nImm A 2\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(program[0], Opcode::Jmp(Bit13Literal(2)).into());
+}
+
+#[test]
+fn backward_label_on_instruction_line() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let source = "Imm A 1\nloop: Sub A A A\nJnz loop\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(program[2], Opcode::Jnz(Bit13Literal(1)).into());
+}
+
+#[test]
+fn unknown_label() {
+    use crate::assemble;
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let source = "Jmp nowhere\n".to_string();
+    assert!(assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).is_err());
+}
+
+#[test]
+fn load_32_bit_constant() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Bit16Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "Li A 0xDEADBEEF\nLi B 42\nJmp end\nend:\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(
+        program[..4],
+        [
+            Opcode::ImmLo(Register::A, Bit16Literal(0xBEEF)).into(),
+            Opcode::ImmHi(Register::A, Bit16Literal(0xDEAD)).into(),
+            Opcode::ImmLo(Register::B, Bit16Literal(42)).into(),
+            Opcode::Jmp(Bit13Literal(4)).into(),
+        ]
+    );
+}
+
+#[test]
+fn negative_literals() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Bit16Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "Li A -5\nLi B -100000\nLoad C SP -2\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(
+        program[..4],
+        [
+            Opcode::SImm(Register::A, Bit13Literal::from_signed(-5).unwrap()).into(),
+            Opcode::ImmLo(Register::B, Bit16Literal(0x7960)).into(),
+            Opcode::ImmHi(Register::B, Bit16Literal(0xfffe)).into(),
+            Opcode::Load(Register::C, Register::SP, Bit13Literal(0x1ffe)).into(),
+        ]
+    );
+
+    // Imm zero extends, so negative values are rejected
+    assert!(assemble(
+        "test.casm".to_string(),
+        "Imm A -1\n".to_string(),
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+    assert!(assemble(
+        "test.casm".to_string(),
+        "SImm A 4096\n".to_string(),
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+}
+
+#[test]
+fn functions_after_top_level() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "Fn five\nImm A 5\nEndFn\nCall five\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(
+        program,
+        [
+            Opcode::Call(Bit13Literal(4)).into(),
+            Opcode::Imm(Register::A, Bit13Literal(0)).into(),
+            Opcode::Imm(Register::B, Bit13Literal(0)).into(),
+            Opcode::Syscall.into(),
+            Opcode::Imm(Register::A, Bit13Literal(5)).into(),
+            Opcode::Ret.into(),
+        ]
+    );
+
+    let unterminated = "Fn five\nImm A 5\nRet\n".to_string();
+    assert!(assemble(
+        "test.casm".to_string(),
+        unterminated,
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+}
+
+#[test]
+fn frame_relative_addressing() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "Load A [FP - 3]\nStore H [FP+1]\nLoad E [SP]\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    let expected = [
+        Opcode::Load(
+            Register::A,
+            Register::FP,
+            Bit13Literal::from_signed(-3).unwrap(),
+        ),
+        Opcode::Store(Register::H, Register::FP, Bit13Literal(1)),
+        Opcode::Load(Register::E, Register::SP, Bit13Literal(0)),
+    ];
+    for (word, ins) in program.iter().zip(expected) {
+        assert_eq!(*word, u32::from(ins));
+    }
+    assert_eq!(expected[0].to_string(), "Load A [FP - 3]");
+
+    assert!(assemble(
+        "test.casm".to_string(),
+        "Load A [FP - 2\n".to_string(),
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+    assert!(assemble(
+        "test.casm".to_string(),
+        "Load A [X + 2]\n".to_string(),
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+}
+
+#[test]
+fn float_literals() {
+    use crate::assemble;
+    use common::instructions::{Bit16Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    // 1.5 is 0x3fc00000, -2.0 is 0xc0000000
+    let source = "Li A 1.5\nLi B -2.0\nLi C 0.0\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(
+        program[..5],
+        [
+            Opcode::ImmLo(Register::A, Bit16Literal(0)).into(),
+            Opcode::ImmHi(Register::A, Bit16Literal(0x3fc0)).into(),
+            Opcode::ImmLo(Register::B, Bit16Literal(0)).into(),
+            Opcode::ImmHi(Register::B, Bit16Literal(0xc000)).into(),
+            Opcode::ImmLo(Register::C, Bit16Literal(0)).into(),
+        ]
+    );
+    assert!(assemble(
+        "test.casm".to_string(),
+        "Li A 1.5.0\n".to_string(),
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+}
+
+#[test]
+fn macros() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "\
+macro countdown reg
+@loop: Sub reg B reg
+    Jnz @loop
+endmacro
+macro twice reg
+    countdown reg
+    countdown reg
+endmacro
+start: twice C
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    // Every expansion jumps back to its own loop
+    assert_eq!(
+        program[..4],
+        [
+            Opcode::Sub(Register::C, Register::B, Register::C).into(),
+            Opcode::Jnz(Bit13Literal(0)).into(),
+            Opcode::Sub(Register::C, Register::B, Register::C).into(),
+            Opcode::Jnz(Bit13Literal(2)).into(),
+        ]
+    );
+
+    // A parameter named like an opcode only replaces operands
+    let shadowing = "macro load Add\nAdd Add B C\nendmacro\nload A\n".to_string();
+    let program = assemble("test.casm".to_string(), shadowing, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+    assert_eq!(
+        program[0],
+        Opcode::Add(Register::A, Register::B, Register::C).into()
+    );
+
+    let wrong_arity = "macro nop\nAdd A A A\nendmacro\nnop A\n".to_string();
+    assert!(assemble(
+        "test.casm".to_string(),
+        wrong_arity,
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+    let recursive = "macro forever\nforever\nendmacro\nforever\n".to_string();
+    assert!(assemble("test.casm".to_string(), recursive, &[], DEFAULT_MEMORY_SIZE).is_err());
+}
+
+#[test]
+fn include_files() {
+    use crate::assemble;
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let dir = std::env::temp_dir().join(format!("crassembler_include_{}", std::process::id()));
+    let lib = dir.join("lib");
+    std::fs::create_dir_all(&lib).unwrap();
+    std::fs::write(
+        lib.join("exit.casm"),
+        "macro exit code\nImm A 0\nImm B code\nSyscall\nendmacro\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("loop.casm"), "include \"loop.casm\"\n").unwrap();
+    std::fs::write(dir.join("bad.casm"), "; Too big\nImm A 99999\n").unwrap();
+    let main = dir.join("main.casm").to_string_lossy().into_owned();
+
+    let source = "include \"exit.casm\"\nexit 3\n".to_string();
+    let program = assemble(main.clone(), source.clone(), &[lib], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+    assert_eq!(program.len(), 6);
+    assert!(assemble(main.clone(), source, &[], DEFAULT_MEMORY_SIZE).is_err());
+
+    let cycle = "include \"loop.casm\"\n".to_string();
+    assert!(assemble(main.clone(), cycle, &[], DEFAULT_MEMORY_SIZE).is_err());
+
+    // Errors point into the included file and through the include chain
+    let error = assemble(
+        main.clone(),
+        "Imm A 1\ninclude \"bad.casm\"\n".to_string(),
+        &[],
+        DEFAULT_MEMORY_SIZE,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.starts_with(&format!("{}:2:", dir.join("bad.casm").display())));
+    assert!(error.ends_with(&format!("included from {}:2", main)));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn data_directives() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "\
+Imm C message
+Jmp end
+.data
+table: .word 1 -1 end
+message: .string \"hi there\\n\\\"\"
+.zero 2
+.text
+end:
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap();
+
+    assert_eq!(
+        program.code[..2],
+        [
+            Opcode::Imm(Register::C, Bit13Literal(3)).into(),
+            Opcode::Jmp(Bit13Literal(2)).into(),
+        ]
+    );
+    let mut data = vec![1, u32::MAX, 2];
+    data.extend("hi there\n\"".chars().map(|c| c as u32));
+    data.extend([0, 0]);
+    assert_eq!(program.data, data);
+
+    let bad_escape = ".data\n.string \"\\q\"\n".to_string();
+    assert!(assemble(
+        "test.casm".to_string(),
+        bad_escape,
+        &[],
+        DEFAULT_MEMORY_SIZE
+    )
+    .is_err());
+
+    // Sizes past the memory are errors instead of huge allocations
+    for huge in [
+        ".zero 0xffffffff",
+        ".zero 0x10000000000",
+        ".zero 0x300000\n.zero 0x300000",
+    ] {
+        let source = format!(".data\n{}\n", huge);
+        assert!(assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).is_err());
+    }
+    // The limit follows the memory the program runs with
+    let source = ".data\n.zero 16\n".to_string();
+    assert!(assemble("test.casm".to_string(), source.clone(), &[], 16).is_ok());
+    assert!(assemble("test.casm".to_string(), source, &[], 15).is_err());
+}
+
+#[test]
+fn constant_expressions() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Bit16Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "\
+% size 6
+macro twice n
+Imm G (n * 2)
+endmacro
+start: Imm A (size * 2 + 1)
+Imm B 'A'
+Imm C (end - start)
+Load D [FP - (size - 3)]
+Li E (1 << 20 | 'a')
+twice (size + 1)
+end:
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+
+    assert_eq!(
+        program[..7],
+        [
+            Opcode::Imm(Register::A, Bit13Literal(13)).into(),
+            Opcode::Imm(Register::B, Bit13Literal(65)).into(),
+            Opcode::Imm(Register::C, Bit13Literal(7)).into(),
+            Opcode::Load(
+                Register::D,
+                Register::FP,
+                Bit13Literal::from_signed(-3).unwrap()
+            )
+            .into(),
+            Opcode::ImmLo(Register::E, Bit16Literal(97)).into(),
+            Opcode::ImmHi(Register::E, Bit16Literal(16)).into(),
+            Opcode::Imm(Register::G, Bit13Literal(14)).into(),
+        ]
+    );
+
+    let error = |source: &str| {
+        assemble(
+            "test.casm".to_string(),
+            source.to_string(),
+            &[],
+            DEFAULT_MEMORY_SIZE,
+        )
+        .unwrap_err()
+        .to_string()
+    };
+    assert!(error("Imm A (1 / 0)\n").contains("Division by zero"));
+    assert!(error("Imm A (8191 + 1)\n").contains("doesn't fit in 13 bits"));
+    assert!(error("ImmLo A (1 << 16)\n").contains("doesn't fit in 16 bits"));
+    assert!(error("Imm A 1+\n").contains("Unexpected end"));
+    assert!(error("Imm A (missing * 2)\n").contains("Unknown label"));
+    assert!(error("Imm A 1 2\n").contains("Too many arguments"));
+}
+
+#[test]
+fn expressions_without_parentheses() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "\
+% SIZE 10
+% DOUBLE (SIZE * 2)
+% TRIPLE SIZE * 3
+Imm A DOUBLE
+Imm B TRIPLE - 1
+Imm C SIZE * 2 + 1
+Load D FP SIZE - 12
+Jmp end - 1
+end:
+.data
+.zero SIZE + 2
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap();
+
+    assert_eq!(
+        program.code[..5],
+        [
+            Opcode::Imm(Register::A, Bit13Literal(20)).into(),
+            Opcode::Imm(Register::B, Bit13Literal(29)).into(),
+            Opcode::Imm(Register::C, Bit13Literal(21)).into(),
+            Opcode::Load(
+                Register::D,
+                Register::FP,
+                Bit13Literal::from_signed(-2).unwrap()
+            )
+            .into(),
+            Opcode::Jmp(Bit13Literal(4)).into(),
+        ]
+    );
+    assert_eq!(program.data, [0; 12]);
+}
+
+#[test]
+fn reports_every_error() {
+    use crate::{assemble, Severity};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let source = "\
+% size 1
+% size 2
+Imm A 99999
+Foo B
+Imm C size
+Jmp nowhere
+.data
+.zero -1
+"
+    .to_string();
+    let diagnostics =
+        assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap_err();
+    assert_eq!(diagnostics.count(Severity::Error), 4);
+    assert_eq!(diagnostics.count(Severity::Warning), 1);
+
+    let report = diagnostics.to_string();
+    for expected in [
+        "test.casm:2:3: warning:",
+        "test.casm:3:7: error:",
+        "test.casm:4:1: error:",
+    ] {
+        assert!(report.contains(expected));
+    }
+
+    // Warnings alone don't stop the assembler
+    let source = "% size 1\n% size 2\nImm A size\n".to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap();
+    assert_eq!(program.warnings.len(), 1);
+}
+
+#[test]
+fn source_positions() {
+    use crate::assemble;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::registers::Register;
+
+    let source = "\
+; Comment lines don't shift the line numbers
+    Imm A 1 ; Comments can follow an instruction
+\tImm B ';' ;and can't start inside a quote
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap()
+        .code;
+    assert_eq!(
+        program[..2],
+        [
+            Opcode::Imm(Register::A, Bit13Literal(1)).into(),
+            Opcode::Imm(Register::B, Bit13Literal(';' as u16)).into(),
+        ]
+    );
+
+    let source = "; Comment\n\n    Jmp   nowhere ; Indented\n".to_string();
+    let error = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error,
+        [
+            "test.casm:3:11: error: Unknown label",
+            "       Jmp   nowhere ; Indented",
+            "             ^~~~~~~",
+        ]
+        .join("\n")
+    );
+
+    // Operands joined into one expression are underlined together
+    let source = "Imm A 2 * nowhere\n".to_string();
+    let error = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE)
+        .unwrap_err()
+        .to_string();
+    assert!(error.ends_with(" ^~~~~~~~~~~"));
+}
+
+#[test]
+fn object_files() {
+    use crate::assemble;
+    use common::bytecode::{BytecodeError, SectionKind};
+    use common::machine::DEFAULT_MEMORY_SIZE;
+    use common::object::{Binding, Object, Relocation, RelocationKind, RelocationTarget};
+
+    let source = "\
+.global main table
+.extern helper
+main: Call helper
+Jmp main
+Li A table
+Imm B (end - main)
+end:
+.data
+table: .word helper 1
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap();
+
+    let symbols: Vec<_> = program
+        .symbols
+        .iter()
+        .map(|s| (s.name.as_str(), s.binding, s.section, s.value))
+        .collect();
+    assert_eq!(
+        symbols,
+        [
+            ("main", Binding::Export, Some(SectionKind::Code), 0),
+            ("end", Binding::Local, Some(SectionKind::Code), 5),
+            ("table", Binding::Export, Some(SectionKind::Data), 0),
+            ("helper", Binding::Import, None, 0),
+        ]
+    );
+
+    let relocation = |section, offset, kind, target| Relocation {
+        section,
+        offset,
+        kind,
+        target,
+        addend: 0,
+    };
+    let code = RelocationTarget::Section(SectionKind::Code);
+    let data = RelocationTarget::Section(SectionKind::Data);
+    let helper = RelocationTarget::Symbol(3);
+    assert_eq!(
+        program.relocations,
+        [
+            relocation(SectionKind::Code, 0, RelocationKind::Lit13, helper),
+            relocation(SectionKind::Code, 1, RelocationKind::Lit13, code),
+            relocation(SectionKind::Code, 2, RelocationKind::Lo16, data),
+            relocation(SectionKind::Code, 3, RelocationKind::Hi16, data),
+            relocation(SectionKind::Data, 0, RelocationKind::Word, helper),
+        ]
+    );
+    assert_eq!(program.entry, Some(0));
+
+    let object = program.to_object();
+    assert_eq!(
+        Object::from_bytes(&object.to_bytes().unwrap()).unwrap(),
+        object
+    );
+
+    // Names have a 16 bit length in the file
+    let mut long_name = object.clone();
+    long_name.symbols[0].name = "a".repeat(u16::MAX as usize + 1);
+    assert!(matches!(
+        long_name.to_bytes(),
+        Err(BytecodeError::NameTooLong(_))
+    ));
+
+    // Libraries without top level code get no entry point and no implicit exit
+    let library = ".global f\nFn f\nRet\nEndFn\n".to_string();
+    let program = assemble("test.casm".to_string(), library, &[], DEFAULT_MEMORY_SIZE).unwrap();
+    assert_eq!(program.entry, None);
+    assert_eq!(program.code.len(), 2);
+
+    let error = |source: &str| {
+        assemble(
+            "test.casm".to_string(),
+            source.to_string(),
+            &[],
+            DEFAULT_MEMORY_SIZE,
+        )
+        .unwrap_err()
+        .to_string()
+    };
+    assert!(error(".global nowhere\nImm A 1\n").contains("never defined"));
+    assert!(error(".extern here\nhere: Imm A 1\n").contains("defined in this file"));
+    assert!(error("start: Imm A (start * 2)\n").contains("Labels can only be"));
+}
+
+#[test]
+fn listing() {
+    use crate::assemble;
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let source = "\
+% size 10
+macro load reg value
+    Imm reg value
+endmacro
+macro load2 a b
+    load a 1
+    load b 2 ; second
+endmacro
+start: Imm A size ; comment
+    load B 3
+    load2 C D
+Fn f
+    Li C 0x12345
+EndFn
+.data
+text: .string \"abc\"
+"
+    .to_string();
+    let program = assemble("test.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap();
+    let listing = program.listing.render(&program.symbols);
+
+    let expected = [
+        "Code",
+        "0000  0000a504           test.casm:9        start: Imm A size",
+        "                                            = start: Imm A 10",
+        // Expanded lines go under the invocation, nested ones under theirs
+        "0001                     test.casm:10       load B 3",
+        "0001  00003604           test.casm:3          Imm reg value",
+        "                                              = Imm B 3",
+        "0002                     test.casm:11       load2 C D",
+        "0002                     test.casm:6          load a 1",
+        "0002  00001704           test.casm:3            Imm reg value",
+        "                                                = Imm C 1",
+        "0003                     test.casm:7          load b 2",
+        "0003  00002804           test.casm:3            Imm reg value",
+        "                                                = Imm D 2",
+        "0004  00000504           <implicit exit>:1  Imm A 0",
+        "0005  00000604           <implicit exit>:2  Imm B 0",
+        "0006  00000018           <implicit exit>:3  Syscall",
+        "0007                     test.casm:12       Fn f",
+        "0007  02345729 0000172a  test.casm:13       Li C 0x12345",
+        "0009  00000016           test.casm:14       EndFn",
+        "                                            = Ret",
+        "Data",
+        "0000  00000061 00000062  test.casm:16       text: .string \"abc\"",
+        "0002  00000063",
+        "",
+        "Symbols",
+        "0000  start            code label",
+        "0007  f                function",
+        "0000  text             data label",
+        "",
+        "Defines",
+        "size             10",
+        "",
+    ];
+    assert_eq!(listing, expected.join("\n"));
+}
+
+#[test]
+fn debug_info() {
+    use crate::assemble;
+    use common::bytecode::BytecodeError;
+    use common::debug_info::DebugInfo;
+    use common::machine::DEFAULT_MEMORY_SIZE;
+
+    let source = "\
+Call f
+Fn f
+    Imm B 1
+print_loop:
+    Pop A ; underflows
+    Jmp print_loop
+EndFn
+"
+    .to_string();
+    let program = assemble("lib.casm".to_string(), source, &[], DEFAULT_MEMORY_SIZE).unwrap();
+    let info = program.listing.debug_info(&program.symbols);
+
+    // Call, the implicit exit, then f
+    assert_eq!(info.locate(0).to_string(), "0 (lib.casm:1)");
+    assert_eq!(info.locate(2).to_string(), "2 (<implicit exit>:2)");
+    assert_eq!(info.locate(4).to_string(), "f (lib.casm:3)");
+    assert_eq!(info.locate(5).to_string(), "print_loop (lib.casm:5)");
+    assert_eq!(info.locate(6).to_string(), "print_loop+1 (lib.casm:6)");
+    assert_eq!(info.locate(7).to_string(), "print_loop+2 (lib.casm:7)");
+    assert_eq!(info.lines[4].column, 5);
+
+    let read = DebugInfo::from_bytes(&info.to_bytes().unwrap()).unwrap();
+    assert_eq!(read, info);
+
+    // File names have a 16 bit length in the file
+    let mut long_name = info.clone();
+    long_name.files[0] = "a".repeat(u16::MAX as usize + 1);
+    assert!(matches!(
+        long_name.to_bytes(),
+        Err(BytecodeError::NameTooLong(_))
+    ));
+}
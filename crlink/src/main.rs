@@ -0,0 +1,326 @@
+mod tests;
+
+use clap::Parser;
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+use common::{
+    archive::{Archive, Member, ARCHIVE_MAGIC},
+    bytecode::{Executable, Section, SectionKind},
+    debug_info::{sidecar_path, DebugInfo, DebugSymbol},
+    object::{Binding, Object, RelocationTarget},
+};
+
+// Linker for crassembler object files
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Object files and `.calib` archives to link, the objects' code and data are laid out
+    /// in this order, followed by the archive members they need
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Output executable filename
+    #[arg(short, long = "output")]
+    output_file: String,
+
+    /// Bundle the object files into a `.calib` archive instead of linking them
+    #[arg(short, long)]
+    archive: bool,
+
+    /// Also write debug info next to the output in `<output>.dbg`, with the line numbers
+    /// of the objects assembled with `--debug-info`
+    #[arg(short = 'g', long = "debug-info", default_value_t = false)]
+    debug_info: bool,
+}
+
+#[derive(Debug)]
+enum LinkError {
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    UndefinedSymbol {
+        name: String,
+        file: String,
+    },
+    NoEntry,
+    MultipleEntries {
+        first: String,
+        second: String,
+    },
+    DoesntFit {
+        file: String,
+        section: SectionKind,
+        offset: u32,
+        target: String,
+        value: i64,
+    },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol {
+                name,
+                first,
+                second,
+            } => write!(f, "`{}` is exported by both {} and {}", name, first, second),
+            LinkError::UndefinedSymbol { name, file } => {
+                write!(f, "{} imports `{}`, but no object exports it", file, name)
+            }
+            LinkError::NoEntry => write!(f, "No object has top level code to start from"),
+            LinkError::MultipleEntries { first, second } => write!(
+                f,
+                "Both {} and {} have top level code, only one can be the entry point",
+                first, second
+            ),
+            LinkError::DoesntFit {
+                file,
+                section,
+                offset,
+                target,
+                value,
+            } => write!(
+                f,
+                "{}: {:?} word {} refers to {}, its address {} doesn't fit in the field",
+                file, section, offset, target, value
+            ),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+/// Appends the archive members exporting a symbol the objects import and nobody defines yet,
+/// until every import is resolved or no archive has what's missing.
+/// Pulled members are named `archive(member)`
+fn resolve_archives(
+    mut objects: Vec<(String, Object)>,
+    archives: &[(String, Archive)],
+) -> Vec<(String, Object)> {
+    let mut pulled = HashSet::new();
+    loop {
+        let exported: HashSet<&str> = objects
+            .iter()
+            .flat_map(|(_, object)| &object.symbols)
+            .filter(|symbol| symbol.binding == Binding::Export)
+            .map(|symbol| symbol.name.as_str())
+            .collect();
+        let needed = objects
+            .iter()
+            .flat_map(|(_, object)| &object.symbols)
+            .filter(|symbol| symbol.binding == Binding::Import)
+            .filter(|symbol| !exported.contains(symbol.name.as_str()))
+            .find_map(|symbol| {
+                archives.iter().enumerate().find_map(|(i, (_, archive))| {
+                    archive
+                        .member_exporting(&symbol.name)
+                        .map(|member| (i, member))
+                        .filter(|member| !pulled.contains(member))
+                })
+            });
+
+        let Some((i, member)) = needed else {
+            return objects;
+        };
+        pulled.insert((i, member));
+        let (file, archive) = &archives[i];
+        let member = &archive.members[member];
+        objects.push((format!("{}({})", file, member.name), member.object.clone()));
+    }
+}
+
+/// Lays the objects out one after the other, code from address 0 in ROM and data
+/// from address 0 in RAM, then rewrites every relocated field with the final addresses
+fn link(objects: &[(String, Object)]) -> Result<Executable, LinkError> {
+    let mut code = vec![];
+    let mut data = vec![];
+    // Where the code and data of every object starts
+    let mut bases = vec![];
+    for (_, object) in objects {
+        bases.push((code.len() as i64, data.len() as i64));
+        code.extend_from_slice(&object.code);
+        data.extend_from_slice(&object.data);
+    }
+    let base = |object: usize, section: SectionKind| match section {
+        SectionKind::Code => bases[object].0,
+        SectionKind::Data => bases[object].1,
+    };
+
+    let mut exports: HashMap<&str, (i64, &str)> = HashMap::new();
+    for (i, (file, object)) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let Some(section) = symbol.section.filter(|_| symbol.binding == Binding::Export) else {
+                continue;
+            };
+            let address = base(i, section) + symbol.value as i64;
+            if let Some((_, first)) = exports.insert(&symbol.name, (address, file)) {
+                return Err(LinkError::DuplicateSymbol {
+                    name: symbol.name.clone(),
+                    first: first.to_string(),
+                    second: file.clone(),
+                });
+            }
+        }
+    }
+
+    let mut entry: Option<(u32, &str)> = None;
+    for (i, (file, object)) in objects.iter().enumerate() {
+        let Some(offset) = object.entry else {
+            continue;
+        };
+        if let Some((_, first)) = entry {
+            return Err(LinkError::MultipleEntries {
+                first: first.to_string(),
+                second: file.clone(),
+            });
+        }
+        entry = Some(((base(i, SectionKind::Code) + offset as i64) as u32, file));
+    }
+    let (entry, _) = entry.ok_or(LinkError::NoEntry)?;
+
+    for (i, (file, object)) in objects.iter().enumerate() {
+        for relocation in &object.relocations {
+            let (target, name) = match relocation.target {
+                RelocationTarget::Section(section) => {
+                    (base(i, section), format!("its {:?} section", section))
+                }
+                RelocationTarget::Symbol(index) => {
+                    let symbol = &object.symbols[index as usize];
+                    let address = match symbol.section {
+                        Some(section) => base(i, section) + symbol.value as i64,
+                        None => match exports.get(symbol.name.as_str()) {
+                            Some((address, _)) => *address,
+                            None => {
+                                return Err(LinkError::UndefinedSymbol {
+                                    name: symbol.name.clone(),
+                                    file: file.clone(),
+                                })
+                            }
+                        },
+                    };
+                    (address, format!("`{}`", symbol.name))
+                }
+            };
+
+            let value = target + relocation.addend as i64;
+            let words = match relocation.section {
+                SectionKind::Code => &mut code,
+                SectionKind::Data => &mut data,
+            };
+            let at = (base(i, relocation.section) + relocation.offset as i64) as usize;
+            words[at] =
+                relocation
+                    .kind
+                    .patch(words[at], value)
+                    .ok_or_else(|| LinkError::DoesntFit {
+                        file: file.clone(),
+                        section: relocation.section,
+                        offset: relocation.offset,
+                        target: name,
+                        value,
+                    })?;
+        }
+    }
+
+    let mut executable = Executable::new(code);
+    executable.entry = entry;
+    if !data.is_empty() {
+        executable.sections.push(Section {
+            kind: SectionKind::Data,
+            address: 0,
+            data,
+        });
+    }
+
+    Ok(executable)
+}
+
+/// Debug info of the linked program: the code labels of every object, and the lines
+/// of the objects that have debug info of their own
+fn link_debug_info(
+    objects: &[(String, Object)],
+    sidecars: &HashMap<String, DebugInfo>,
+) -> DebugInfo {
+    let mut info = DebugInfo::default();
+    let mut base = 0;
+    for (file, object) in objects {
+        let mut object_info = sidecars.get(file).cloned().unwrap_or_default();
+        object_info.symbols = object
+            .symbols
+            .iter()
+            .filter(|s| s.section == Some(SectionKind::Code))
+            .map(|s| DebugSymbol {
+                name: s.name.clone(),
+                address: s.value,
+            })
+            .collect();
+        info.append(&object_info, base);
+        base += object.code.len() as u32;
+    }
+
+    info
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let mut objects = vec![];
+    let mut archives = vec![];
+    let mut sidecars = HashMap::new();
+    for file in args.inputs {
+        let bytes = std::fs::read(&file).map_err(|e| format!("{}: {}", file, e))?;
+        if bytes.starts_with(&ARCHIVE_MAGIC) {
+            let archive = Archive::from_bytes(&bytes).map_err(|e| format!("{}: {}", file, e))?;
+            archives.push((file, archive));
+        } else {
+            let object = Object::from_bytes(&bytes).map_err(|e| format!("{}: {}", file, e))?;
+            let sidecar = sidecar_path(&file);
+            if let (true, Ok(bytes)) = (args.debug_info, std::fs::read(&sidecar)) {
+                let info =
+                    DebugInfo::from_bytes(&bytes).map_err(|e| format!("{}: {}", sidecar, e))?;
+                sidecars.insert(file.clone(), info);
+            }
+            objects.push((file, object));
+        }
+    }
+
+    if args.archive {
+        if let Some((file, _)) = archives.first() {
+            return Err(format!("{}: archives can't be nested", file).into());
+        }
+        let members = objects
+            .into_iter()
+            .map(|(file, object)| Member {
+                name: Path::new(&file)
+                    .file_name()
+                    .map_or(file.clone(), |name| name.to_string_lossy().into_owned()),
+                object,
+            })
+            .collect();
+        let archive = Archive::new(members)
+            .map_err(|name| format!("`{}` is exported by more than one object", name))?;
+        std::fs::write(args.output_file, archive.to_bytes()?)?;
+        return Ok(());
+    }
+
+    let objects = resolve_archives(objects, &archives);
+    let executable = match link(&objects) {
+        Ok(executable) => executable,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    std::fs::write(&args.output_file, executable.to_bytes())?;
+    if args.debug_info {
+        let info = link_debug_info(&objects, &sidecars);
+        std::fs::write(sidecar_path(&args.output_file), info.to_bytes()?)?;
+    }
+
+    Ok(())
+}
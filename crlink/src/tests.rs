@@ -0,0 +1,333 @@
+#[cfg(test)]
+#[test]
+fn links_across_objects() {
+    use crate::link;
+    use common::bytecode::SectionKind;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::object::{Binding, Object, Relocation, RelocationKind, RelocationTarget, Symbol};
+    use common::registers::Register;
+
+    // square: Mul A A A, Ret; table: .word table
+    let lib = Object {
+        code: vec![
+            Opcode::Mul(Register::A, Register::A, Register::A).into(),
+            Opcode::Ret.into(),
+        ],
+        data: vec![0],
+        symbols: vec![
+            Symbol {
+                name: "square".to_string(),
+                binding: Binding::Export,
+                section: Some(SectionKind::Code),
+                value: 0,
+            },
+            Symbol {
+                name: "table".to_string(),
+                binding: Binding::Export,
+                section: Some(SectionKind::Data),
+                value: 0,
+            },
+        ],
+        relocations: vec![Relocation {
+            section: SectionKind::Data,
+            offset: 0,
+            kind: RelocationKind::Word,
+            target: RelocationTarget::Section(SectionKind::Data),
+            addend: 0,
+        }],
+        ..Default::default()
+    };
+
+    // Imm A 3, Call square, loop: Jmp loop; .word 5 (table + 1)
+    let main = Object {
+        entry: Some(0),
+        code: vec![
+            Opcode::Imm(Register::A, Bit13Literal(3)).into(),
+            Opcode::Call(Bit13Literal(0)).into(),
+            Opcode::Jmp(Bit13Literal(2)).into(),
+        ],
+        data: vec![5, 1],
+        symbols: vec![
+            Symbol {
+                name: "square".to_string(),
+                binding: Binding::Import,
+                section: None,
+                value: 0,
+            },
+            Symbol {
+                name: "table".to_string(),
+                binding: Binding::Import,
+                section: None,
+                value: 0,
+            },
+        ],
+        relocations: vec![
+            Relocation {
+                section: SectionKind::Code,
+                offset: 1,
+                kind: RelocationKind::Lit13,
+                target: RelocationTarget::Symbol(0),
+                addend: 0,
+            },
+            Relocation {
+                section: SectionKind::Code,
+                offset: 2,
+                kind: RelocationKind::Lit13,
+                target: RelocationTarget::Section(SectionKind::Code),
+                addend: 2,
+            },
+            Relocation {
+                section: SectionKind::Data,
+                offset: 1,
+                kind: RelocationKind::Word,
+                target: RelocationTarget::Symbol(1),
+                addend: 1,
+            },
+        ],
+        ..Default::default()
+    };
+
+    let objects = [("lib.o".to_string(), lib), ("main.o".to_string(), main)];
+    let executable = link(&objects).unwrap();
+
+    assert_eq!(executable.entry, 2);
+    assert_eq!(
+        executable.code().unwrap().data[2..],
+        [
+            Opcode::Imm(Register::A, Bit13Literal(3)).into(),
+            Opcode::Call(Bit13Literal(0)).into(),
+            Opcode::Jmp(Bit13Literal(4)).into(),
+        ]
+    );
+    assert_eq!(executable.data().next().unwrap().data, [0, 5, 1]);
+}
+
+#[test]
+fn link_errors() {
+    use crate::{link, LinkError};
+    use common::bytecode::SectionKind;
+    use common::object::{Binding, Object, Relocation, RelocationKind, RelocationTarget, Symbol};
+
+    let export = |name: &str| Symbol {
+        name: name.to_string(),
+        binding: Binding::Export,
+        section: Some(SectionKind::Code),
+        value: 0,
+    };
+    let with_entry = Object {
+        entry: Some(0),
+        code: vec![0],
+        ..Default::default()
+    };
+    let importer = Object {
+        entry: Some(0),
+        code: vec![0],
+        symbols: vec![Symbol {
+            name: "missing".to_string(),
+            binding: Binding::Import,
+            section: None,
+            value: 0,
+        }],
+        relocations: vec![Relocation {
+            section: SectionKind::Code,
+            offset: 0,
+            kind: RelocationKind::Lit13,
+            target: RelocationTarget::Symbol(0),
+            addend: 0,
+        }],
+        ..Default::default()
+    };
+    let library = Object {
+        code: vec![0],
+        symbols: vec![export("f")],
+        ..Default::default()
+    };
+
+    let result = link(&[("a.o".to_string(), importer)]);
+    assert!(matches!(result, Err(LinkError::UndefinedSymbol { .. })));
+
+    let result = link(&[("a.o".to_string(), library.clone())]);
+    assert!(matches!(result, Err(LinkError::NoEntry)));
+
+    let result = link(&[
+        ("a.o".to_string(), with_entry.clone()),
+        ("b.o".to_string(), with_entry),
+    ]);
+    assert!(matches!(result, Err(LinkError::MultipleEntries { .. })));
+
+    let result = link(&[
+        ("a.o".to_string(), library.clone()),
+        ("b.o".to_string(), library),
+    ]);
+    assert!(matches!(result, Err(LinkError::DuplicateSymbol { .. })));
+
+    // A jump target past the 13 bit range
+    let far = Object {
+        entry: Some(0),
+        code: vec![0],
+        relocations: vec![Relocation {
+            section: SectionKind::Code,
+            offset: 0,
+            kind: RelocationKind::Lit13,
+            target: RelocationTarget::Section(SectionKind::Code),
+            addend: 8192,
+        }],
+        ..Default::default()
+    };
+    let result = link(&[("a.o".to_string(), far)]);
+    assert!(matches!(result, Err(LinkError::DoesntFit { .. })));
+}
+
+#[test]
+fn pulls_needed_archive_members() {
+    use crate::{link, resolve_archives};
+    use common::archive::{Archive, Member};
+    use common::bytecode::{BytecodeError, SectionKind, ISA_VERSION};
+    use common::object::{Binding, Object, Relocation, RelocationKind, RelocationTarget, Symbol};
+
+    let symbol = |name: &str, binding| Symbol {
+        name: name.to_string(),
+        binding,
+        section: (binding != Binding::Import).then_some(SectionKind::Code),
+        value: 0,
+    };
+    let call = |symbol| Relocation {
+        section: SectionKind::Code,
+        offset: 0,
+        kind: RelocationKind::Lit13,
+        target: RelocationTarget::Symbol(symbol),
+        addend: 0,
+    };
+
+    let base = Object {
+        isa_version: ISA_VERSION,
+        code: vec![0],
+        ..Default::default()
+    };
+
+    // main calls f, f calls g, h is never used
+    let main = Object {
+        entry: Some(0),
+        symbols: vec![symbol("f", Binding::Import)],
+        relocations: vec![call(0)],
+        ..base.clone()
+    };
+    let member = |name: &str, object| Member {
+        name: name.to_string(),
+        object,
+    };
+    let f = Object {
+        symbols: vec![symbol("f", Binding::Export), symbol("g", Binding::Import)],
+        relocations: vec![call(1)],
+        ..base.clone()
+    };
+    let g = Object {
+        symbols: vec![symbol("g", Binding::Export)],
+        ..base.clone()
+    };
+    let h = Object {
+        symbols: vec![symbol("h", Binding::Export)],
+        ..base.clone()
+    };
+
+    let archive = Archive::new(vec![
+        member("h.o", h.clone()),
+        member("g.o", g),
+        member("f.o", f),
+    ])
+    .unwrap();
+    assert_eq!(archive.member_exporting("f"), Some(2));
+    assert_eq!(archive.member_exporting("main"), None);
+    let read = Archive::from_bytes(&archive.to_bytes().unwrap()).unwrap();
+    assert_eq!(read, archive);
+    // Names have a 16 bit length in the file
+    let long_name = Archive::new(vec![member(&"h".repeat(70_000), h.clone())]).unwrap();
+    assert!(matches!(
+        long_name.to_bytes(),
+        Err(BytecodeError::NameTooLong(_))
+    ));
+
+    let objects = resolve_archives(
+        vec![("main.o".to_string(), main)],
+        &[("std.calib".to_string(), archive)],
+    );
+    let names: Vec<&str> = objects.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["main.o", "std.calib(f.o)", "std.calib(g.o)"]);
+
+    // main at 0, f at 1, g at 2
+    let executable = link(&objects).unwrap();
+    assert_eq!(executable.sections[0].data, vec![1 << 12, 2 << 12, 0]);
+
+    // Two members can't export the same symbol
+    let result = Archive::new(vec![member("a.o", h.clone()), member("b.o", h)]);
+    assert_eq!(result, Err("h".to_string()));
+}
+
+#[test]
+fn merges_debug_info() {
+    use crate::link_debug_info;
+    use common::bytecode::SectionKind;
+    use common::debug_info::{DebugInfo, DebugSymbol, LineEntry};
+    use common::object::{Binding, Object, Symbol};
+    use std::collections::HashMap;
+
+    let label = |name: &str, value| Symbol {
+        name: name.to_string(),
+        binding: Binding::Local,
+        section: Some(SectionKind::Code),
+        value,
+    };
+    let main = Object {
+        code: vec![0; 3],
+        symbols: vec![label("start", 0)],
+        ..Default::default()
+    };
+    let lib = Object {
+        code: vec![0; 2],
+        symbols: vec![label("helper", 0), label("helper_loop", 1)],
+        ..Default::default()
+    };
+
+    // Only the library was assembled with debug info
+    let lib_info = DebugInfo {
+        files: vec!["lib.casm".to_string()],
+        lines: vec![
+            LineEntry {
+                address: 0,
+                file: 0,
+                line: 3,
+                column: 5,
+            },
+            LineEntry {
+                address: 1,
+                file: 0,
+                line: 5,
+                column: 5,
+            },
+        ],
+        symbols: vec![],
+    };
+    let sidecars = HashMap::from([("lib.o".to_string(), lib_info)]);
+    let objects = [("main.o".to_string(), main), ("lib.o".to_string(), lib)];
+    let info = link_debug_info(&objects, &sidecars);
+
+    assert_eq!(
+        info.symbols,
+        [
+            DebugSymbol {
+                name: "start".to_string(),
+                address: 0
+            },
+            DebugSymbol {
+                name: "helper".to_string(),
+                address: 3
+            },
+            DebugSymbol {
+                name: "helper_loop".to_string(),
+                address: 4
+            },
+        ]
+    );
+    assert_eq!(info.locate(1).to_string(), "start+1");
+    assert_eq!(info.locate(4).to_string(), "helper_loop (lib.casm:5)");
+}
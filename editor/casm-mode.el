@@ -2,10 +2,16 @@
   (defconst casm-keywords
     '("Add" "Sub" "Mul" "Div" "Imm" "Push" "Pop"
       "StackAdd" "StackSub" "StackMul" "StackDiv" "Cmp" "Jmp" "Je"
-      "Jne" "Jg" "Jge" "Jl" "Jle" "Jz" "Jnz" "Ret" "Call" "Fn" "Syscall" "PushImm")))
+      "Jne" "Jg" "Jge" "Jl" "Jle" "Jz" "Jnz" "Ret" "Call" "Fn" "EndFn" "macro" "endmacro" "include" "Syscall" "PushImm"
+      "Load" "Store" "And" "Or" "Xor" "Not" "Shl" "Shr" "Sar"
+      "StackAnd" "StackOr" "StackXor" "StackNot" "StackShl" "StackShr" "StackSar"
+      "ImmLo" "ImmHi" "Li" "Jc" "Jnc" "Jo" "Jno" "Js" "Jns"
+      "IMul" "IDiv" "SCmp" "SImm"
+      "FAdd" "FSub" "FMul" "FDiv" "FCmp" "ItoF" "FtoI")))
 
 (defconst casm-highlights
-  `((,(regexp-opt casm-keywords 'symbols) . font-lock-keyword-face)))
+  `((,(regexp-opt casm-keywords 'symbols) . font-lock-keyword-face)
+    ("\\.\\(?:data\\|text\\|word\\|string\\|zero\\|global\\|extern\\)\\_>" . font-lock-preprocessor-face)))
 
 
 ;;;###autoload
@@ -5,14 +5,31 @@ endif
 syn match casmLiteral display "[0-9]"
 syn match casmLiteral display "#[0-f]"
 syn match casmLiteral display "$[0-1]"
-syn match casmComment display ";*."
+syn match casmLiteral display "0x[0-9a-fA-F]"
+syn match casmLiteral display "0b[0-1]"
+syn match casmComment display ";.*$"
+syn region casmString start=+"+ skip=+\\\\\|\\"+ end=+"+
+syn match casmDirective display "\.\(data\|text\|word\|string\|zero\|global\|extern\)\>"
 
 syn keyword casmKeyword
  \ Add
  \ Sub
  \ Mul
  \ Div
+ \ IMul
+ \ IDiv
  \ Imm
+ \ SImm
+ \ FAdd
+ \ FSub
+ \ FMul
+ \ FDiv
+ \ FCmp
+ \ ItoF
+ \ FtoI
+ \ ImmLo
+ \ ImmHi
+ \ Li
  \ PushImm
  \ Push
  \ Pop
@@ -21,6 +38,7 @@ syn keyword casmKeyword
  \ StackMul
  \ StackDiv
  \ Cmp
+ \ SCmp
  \ Jmp
  \ Je
  \ Jne
@@ -30,21 +48,56 @@ syn keyword casmKeyword
  \ Jle
  \ Jz
  \ Jnz
+ \ Jc
+ \ Jnc
+ \ Jo
+ \ Jno
+ \ Js
+ \ Jns
  \ Ret
  \ Call
  \ Fn
+ \ EndFn
+ \ macro
+ \ endmacro
+ \ include
  \ Syscall
+ \ Load
+ \ Store
+ \ And
+ \ Or
+ \ Xor
+ \ Not
+ \ Shl
+ \ Shr
+ \ Sar
+ \ StackAnd
+ \ StackOr
+ \ StackXor
+ \ StackNot
+ \ StackShl
+ \ StackShr
+ \ StackSar
 
 syn keyword casmRegister
  \ A
  \ B
  \ C
  \ D
+ \ E
+ \ F
+ \ G
+ \ H
  \ SP
+ \ FP
  \ PC
+ \ Flag
  \ Zero
 
 hi def link casmLiteral Number
+hi def link casmString String
+hi def link casmComment Comment
+hi def link casmDirective PreProc
 hi def link casmRegister Type
 hi def link casmKeyword Function
 
@@ -41,22 +41,32 @@ pub fn opcode_traits_derive(input: TokenStream) -> TokenStream {
         match &f1[..] {
             ["Register", "Register", "Register"] => {
                 quote! {
-                    #i => Opcode::#ident(value.r1(), value.r2(), value.r3()),
+                    #i => Opcode::#ident(value.r1()?, value.r2()?, value.r3()?),
                 }
             }
             ["Register", "Register"] => {
                 quote! {
-                    #i => Opcode::#ident(value.r1(), value.r2()),
+                    #i => Opcode::#ident(value.r1()?, value.r2()?),
+                }
+            }
+            ["Register", "Register", "Bit13Literal"] => {
+                quote! {
+                    #i => Opcode::#ident(value.r1()?, value.r2()?, value.offset13()),
                 }
             }
             ["Register", "Bit13Literal"] => {
                 quote! {
-                    #i => Opcode::#ident(value.r1(), value.lit13()),
+                    #i => Opcode::#ident(value.r1()?, value.lit13()),
+                }
+            }
+            ["Register", "Bit16Literal"] => {
+                quote! {
+                    #i => Opcode::#ident(value.r1()?, value.lit16()),
                 }
             }
             ["Register"] => {
                 quote! {
-                    #i => Opcode::#ident(value.r1()),
+                    #i => Opcode::#ident(value.r1()?),
                 }
             }
             ["Bit13Literal"] => {
@@ -100,10 +110,18 @@ pub fn opcode_traits_derive(input: TokenStream) -> TokenStream {
             quote! {
                 Opcode::#ident(r1, r2)=> #i.reg_2_instruction(r1, r2),
             }
+        } else if f1[..] == ["Register", "Register", "Bit13Literal"] {
+            quote! {
+                Opcode::#ident(r1, r2, offset)=> #i.mem_instruction(r1, r2, offset),
+            }
         } else if f1[..] == ["Register", "Bit13Literal"] {
             quote! {
                 Opcode::#ident(r1, imm)=> #i.imm_instruction(r1, imm),
             }
+        } else if f1[..] == ["Register", "Bit16Literal"] {
+            quote! {
+                Opcode::#ident(r1, imm)=> #i.imm16_instruction(r1, imm),
+            }
         } else if f1[..] == ["Register"] {
             quote! {
                 Opcode::#ident(r1)=> #i.reg_1_instruction(r1),
@@ -120,12 +138,14 @@ pub fn opcode_traits_derive(input: TokenStream) -> TokenStream {
     });
 
     let gen = quote! {
-        impl From<u32> for Opcode {
-            fn from(value: u32) -> Opcode {
-                match value.op() as u32 {
+        impl TryFrom<u32> for Opcode {
+            type Error = DecodeError;
+
+            fn try_from(value: u32) -> Result<Opcode, DecodeError> {
+                Ok(match value.op() as u32 {
                     #(#from_u32_arms)*
-                    _ => unreachable!(),
-                }
+                    _ => return Err(DecodeError { word: value, field: DecodeField::Opcode }),
+                })
             }
         }
 
@@ -159,14 +179,18 @@ pub fn register_traits_derive(input: TokenStream) -> TokenStream {
             .into();
     };
 
-    // Generate match arms for the `From<u32>` implementation
-    let from_u32_arms = variants.iter().enumerate().map(|(i, v)| {
-        let variant = &v.ident;
-        let i = i as u32;
-        quote! {
-            #i => Register::#variant,
-        }
-    });
+    // Generate match arms for the `TryFrom<u32>` implementation, `Count` is not a real register
+    let from_u32_arms = variants
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| v.ident != "Count")
+        .map(|(i, v)| {
+            let variant = &v.ident;
+            let i = i as u32;
+            quote! {
+                #i => Ok(Register::#variant),
+            }
+        });
 
     // Generate match arms for the `From<Register>` implementation
     let from_register_arms = variants.iter().enumerate().map(|(i, v)| {
@@ -186,11 +210,13 @@ pub fn register_traits_derive(input: TokenStream) -> TokenStream {
     });
 
     let gen = quote! {
-            impl From<u32> for Register {
-                fn from(value: u32) -> Register {
+            impl TryFrom<u32> for Register {
+                type Error = InvalidRegister;
+
+                fn try_from(value: u32) -> Result<Register, InvalidRegister> {
                     match value {
                         #(#from_u32_arms)*
-                        _ => unreachable!(),
+                        _ => Err(InvalidRegister),
                     }
                 }
             }
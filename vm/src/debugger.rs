@@ -0,0 +1,349 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use common::debug_info::DebugInfo;
+use common::instructions::{parse_literal, Opcode};
+use common::machine::{CrazyVM, RuntimeError};
+use common::registers::Register;
+
+use crate::utils::backtrace;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          Execute n instructions (default 1)
+  n, next              Execute one instruction, running called functions to completion
+  c, continue          Run until a breakpoint or the end of the program
+  b, break [addr]      Set a breakpoint at addr, or list breakpoints.
+                       With debug info addr can also be a label
+  d, delete <addr>     Remove the breakpoint at addr
+  r, regs [f]          Print the registers, with f the general purpose ones as floats
+  x, mem <addr> [n]    Print n words of memory starting at addr (default 1)
+  set <reg> <value>    Write value into a register, 1.5 style values are stored as floats
+  w, write <addr> <v>  Write v into memory at addr
+  l, list [n]          Disassemble n instructions around PC (default 5)
+  bt, backtrace        Print the calls that led to PC
+  h, help              Print this message
+  q, quit              Stop debugging";
+
+/// Why the debugger stopped running the program
+enum Stop {
+    Paused,
+    Breakpoint(u32),
+    Exited(u32),
+    Fault(RuntimeError),
+}
+
+/// Interactive command prompt that steps a machine
+pub struct Debugger<'a> {
+    machine: &'a mut CrazyVM,
+    debug_info: Option<&'a DebugInfo>,
+    breakpoints: BTreeSet<u32>,
+    finished: bool,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(machine: &'a mut CrazyVM, debug_info: Option<&'a DebugInfo>) -> Self {
+        Self {
+            machine,
+            debug_info,
+            breakpoints: BTreeSet::new(),
+            finished: false,
+        }
+    }
+
+    pub fn run(&mut self) {
+        println!("crazyVM debugger, type `help` for a list of commands");
+        self.list(2);
+
+        // Locked per line so the program's sys_read can share stdin
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+
+        loop {
+            print!("(cdb) ");
+            std::io::stdout().flush().unwrap();
+
+            line.clear();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.execute(&args) {
+                match e {
+                    CommandError::Quit => break,
+                    CommandError::Usage(usage) => println!("Usage: {}", usage),
+                    CommandError::Invalid(msg) => println!("{}", msg),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn execute(&mut self, args: &[&str]) -> Result<(), CommandError> {
+        match args[0] {
+            "s" | "step" => {
+                let n = match args.get(1) {
+                    Some(n) => parse_number(n)?,
+                    None => 1,
+                };
+                let mut stop = Stop::Paused;
+                for _ in 0..n {
+                    stop = self.step()?;
+                    if !matches!(stop, Stop::Paused) {
+                        break;
+                    }
+                }
+                self.report(stop);
+            }
+            "n" | "next" => {
+                let stop = self.next()?;
+                self.report(stop);
+            }
+            "c" | "continue" => {
+                let stop = self.resume(|_| false)?;
+                self.report(stop);
+            }
+            "b" | "break" => match args.get(1) {
+                Some(addr) => {
+                    let addr = self.parse_address(addr)?;
+                    self.breakpoints.insert(addr);
+                    println!("Breakpoint set at {}", self.describe(addr));
+                }
+                None => {
+                    for addr in &self.breakpoints {
+                        println!("{}", self.describe(*addr));
+                    }
+                }
+            },
+            "d" | "delete" => {
+                let addr =
+                    self.parse_address(args.get(1).ok_or(CommandError::Usage("delete <addr>"))?)?;
+                if !self.breakpoints.remove(&addr) {
+                    return Err(CommandError::Invalid(format!("No breakpoint at {}", addr)));
+                }
+            }
+            "r" | "regs" => match args.get(1) {
+                Some(&"f") => println!("{}", self.machine.registers().floats()),
+                Some(_) => return Err(CommandError::Usage("regs [f]")),
+                None => println!("{}", self.machine.registers()),
+            },
+            "x" | "mem" => {
+                let addr = parse_number(args.get(1).ok_or(CommandError::Usage("mem <addr> [n]"))?)?;
+                let n = match args.get(2) {
+                    Some(n) => parse_number(n)?,
+                    None => 1,
+                };
+                // Past the end of memory is an error anyway, don't allocate for it
+                let n = (n as usize).min(self.machine.memory().max_size());
+                let words = self
+                    .machine
+                    .memory()
+                    .read_many(addr as usize, n)
+                    .map_err(|e| CommandError::Invalid(e.to_string()))?;
+                for (i, word) in words.iter().enumerate() {
+                    println!("{:>8}: {:>10} {:#010x}", addr as usize + i, word, word);
+                }
+            }
+            "set" => {
+                let (reg, value) = match args {
+                    [_, reg, value] => (*reg, *value),
+                    _ => return Err(CommandError::Usage("set <reg> <value>")),
+                };
+                let reg = Register::try_from(reg)
+                    .map_err(|_| CommandError::Invalid(format!("Invalid register name {}", reg)))?;
+                self.machine.registers_mut()[reg] = parse_value(value)?;
+            }
+            "w" | "write" => {
+                let (addr, value) = match args {
+                    [_, addr, value] => (parse_number(addr)?, parse_value(value)?),
+                    _ => return Err(CommandError::Usage("write <addr> <value>")),
+                };
+                self.machine
+                    .memory_mut()
+                    .write(value, addr as usize)
+                    .map_err(|e| CommandError::Invalid(e.to_string()))?;
+            }
+            "l" | "list" => {
+                let n = match args.get(1) {
+                    Some(n) => parse_number(n)?,
+                    None => 5,
+                };
+                self.list(n);
+            }
+            "bt" | "backtrace" => {
+                let pc = self.machine.registers()[Register::PC];
+                println!("{}", backtrace(self.machine, pc, self.debug_info));
+            }
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Err(CommandError::Quit),
+            other => {
+                return Err(CommandError::Invalid(format!(
+                    "Unknown command `{}`, type `help` for a list of commands",
+                    other
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Stop, CommandError> {
+        if self.finished {
+            return Err(CommandError::Invalid(
+                "The program is not running anymore".to_string(),
+            ));
+        }
+
+        let stop = match self.machine.step() {
+            Ok(None) => Stop::Paused,
+            Ok(Some(code)) => Stop::Exited(code),
+            Err(e) => Stop::Fault(e),
+        };
+        if !matches!(stop, Stop::Paused) {
+            self.finished = true;
+        }
+
+        Ok(stop)
+    }
+
+    /// Keeps stepping until `done` says so, a breakpoint is hit or the program ends
+    fn resume(&mut self, done: impl Fn(&CrazyVM) -> bool) -> Result<Stop, CommandError> {
+        loop {
+            let stop = self.step()?;
+            if !matches!(stop, Stop::Paused) {
+                return Ok(stop);
+            }
+
+            let pc = self.machine.registers()[Register::PC];
+            if done(self.machine) {
+                return Ok(Stop::Paused);
+            }
+            if self.breakpoints.contains(&pc) {
+                return Ok(Stop::Breakpoint(pc));
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Stop, CommandError> {
+        let pc = self.machine.registers()[Register::PC];
+        let is_call = self
+            .machine
+            .program()
+            .read(pc as usize)
+            .is_ok_and(|word| matches!(Opcode::try_from(word), Ok(Opcode::Call(_))));
+
+        if !is_call {
+            return self.step();
+        }
+
+        // The call returns once the stack is back where it was and we are past the Call
+        let sp = self.machine.registers()[Register::SP];
+        self.resume(|machine| {
+            machine.registers()[Register::PC] == pc + 1 && machine.registers()[Register::SP] == sp
+        })
+    }
+
+    fn report(&mut self, stop: Stop) {
+        match stop {
+            Stop::Paused => {}
+            Stop::Breakpoint(addr) => println!("Breakpoint hit at {}", self.describe(addr)),
+            Stop::Exited(0) => println!("Program exited succesfully!"),
+            Stop::Exited(n) => println!("Program exited abnormally! Exit code: [{}]", n),
+            Stop::Fault(RuntimeError::NoNextInstruction) => {
+                println!("Program ran out of instructions")
+            }
+            Stop::Fault(e) => {
+                match (e.pc(), self.debug_info) {
+                    (Some(pc), Some(info)) => {
+                        println!("FATAL ERROR: {} at {}", e, info.locate(pc))
+                    }
+                    (Some(pc), None) => println!("FATAL ERROR: {} (PC: {})", e, pc),
+                    (None, _) => println!("FATAL ERROR: {}", e),
+                }
+                if let (Some(pc), false) = (e.pc(), self.machine.call_stack().is_empty()) {
+                    println!("{}", backtrace(self.machine, pc, self.debug_info));
+                }
+            }
+        }
+
+        if !self.finished {
+            self.list(2);
+        }
+    }
+
+    /// Disassembles `n` instructions on each side of PC
+    fn list(&self, n: u32) {
+        let pc = self.machine.registers()[Register::PC];
+        let program = self.machine.program();
+        let end = (pc.saturating_add(n.saturating_add(1)) as usize).min(program.len());
+
+        for addr in pc.saturating_sub(n) as usize..end {
+            let labels = self.debug_info.iter().flat_map(|info| &info.symbols);
+            for label in labels.filter(|s| s.address as usize == addr) {
+                println!("{}:", label.name);
+            }
+            let word = program.read(addr).unwrap();
+            let marker = if addr == pc as usize { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&(addr as u32)) {
+                "*"
+            } else {
+                " "
+            };
+            match Opcode::try_from(word) {
+                Ok(ins) => println!("{}{} {:>6}: {}", marker, breakpoint, addr, ins),
+                Err(e) => println!("{}{} {:>6}: <{}>", marker, breakpoint, addr, e),
+            }
+        }
+    }
+
+    /// `addr`, followed by its label and source line when there is debug info
+    fn describe(&self, addr: u32) -> String {
+        match self.debug_info {
+            Some(info) => format!("{}: {}", addr, info.locate(addr)),
+            None => addr.to_string(),
+        }
+    }
+
+    /// A code address, or the name of a label from the debug info
+    fn parse_address(&self, value: &str) -> Result<u32, CommandError> {
+        let label = self
+            .debug_info
+            .and_then(|info| info.symbols.iter().find(|s| s.name == value));
+        match label {
+            Some(label) => Ok(label.address),
+            None => parse_number(value),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum CommandError {
+    Quit,
+    Usage(&'static str),
+    Invalid(String),
+}
+
+/// Accepts the same number syntax as casm: decimal, `#`/`0x` hex and `$`/`0b` binary,
+/// negative numbers are stored as two's complement
+pub(crate) fn parse_number(value: &str) -> Result<u32, CommandError> {
+    parse_literal(value)
+        .map(|n| n as u32)
+        .map_err(|_| CommandError::Invalid(format!("Invalid number {}", value)))
+}
+
+/// A number, or the bits of an f32 when the value has a decimal point
+fn parse_value(value: &str) -> Result<u32, CommandError> {
+    if !value.contains('.') {
+        return parse_number(value);
+    }
+
+    value
+        .parse::<f32>()
+        .map(f32::to_bits)
+        .map_err(|_| CommandError::Invalid(format!("Invalid number {}", value)))
+}
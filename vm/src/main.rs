@@ -1,8 +1,10 @@
+mod debugger;
 mod tests;
 pub mod utils;
 
 use clap::Parser;
-use common::machine::CrazyVM;
+use common::machine::{CrazyVM, DEFAULT_MEMORY_SIZE};
+use std::process::ExitCode;
 
 use common::machine::RuntimeError;
 
@@ -15,21 +17,44 @@ struct Args {
     input_file: String,
 
     /// Memory available to crazyVM
-    #[arg(short, long = "mem", default_value_t = 1024 * 1024 * 4)]
+    #[arg(short, long = "mem", default_value_t = DEFAULT_MEMORY_SIZE)]
     memory_size: usize,
+
+    /// Read the old hex text format instead of a binary bytecode file
+    #[arg(long, default_value_t = false)]
+    legacy_hex: bool,
+
+    /// Run the program under the interactive debugger
+    #[arg(short, long, default_value_t = false)]
+    debug: bool,
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
-    let program = match utils::read_binary(&args.input_file) {
+    let program = match utils::read_binary(&args.input_file, args.legacy_hex) {
         Some(prog) => prog,
         None => {
             eprintln!("Failed to read bytecode file {}", args.input_file);
-            return;
+            return ExitCode::FAILURE;
         }
     };
 
-    let mut machine = CrazyVM::new(&program, args.memory_size);
+    let debug_info = utils::read_debug_info(&args.input_file);
+
+    let mut machine = match CrazyVM::from_executable(&program, args.memory_size) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("Data section doesn't fit in memory: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if args.debug {
+        debugger::Debugger::new(&mut machine, debug_info.as_ref()).run();
+        return ExitCode::SUCCESS;
+    }
+
+    // Faults exit with an error status, whatever exit code the program asked for doesn't
+    let mut status = ExitCode::SUCCESS;
     loop {
         match machine.step() {
             Ok(None) => {}
@@ -43,10 +68,21 @@ fn main() {
             }
             Err(RuntimeError::NoNextInstruction) => break,
             Err(e) => {
-                eprintln!("FATAL ERROR: {}", e);
+                match (e.pc(), &debug_info) {
+                    (Some(pc), Some(info)) => {
+                        eprintln!("FATAL ERROR: {} at {}", e, info.locate(pc))
+                    }
+                    (Some(pc), None) => eprintln!("FATAL ERROR: {} (PC: {})", e, pc),
+                    (None, _) => eprintln!("FATAL ERROR: {}", e),
+                }
+                if let (Some(pc), false) = (e.pc(), machine.call_stack().is_empty()) {
+                    eprintln!("{}", utils::backtrace(&machine, pc, debug_info.as_ref()));
+                }
+                status = ExitCode::FAILURE;
                 break;
             }
         }
     }
     machine.dump_state();
+    status
 }
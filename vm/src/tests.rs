@@ -38,6 +38,7 @@ fn raw_write_many_fail() {
 
 #[test]
 fn raw_read_fail() {
+    use common::data_structures::error::OutOfBoundsError;
     use common::data_structures::ram::Ram;
 
     let mut ram = Ram::new(1024);
@@ -48,6 +49,20 @@ fn raw_read_fail() {
 
     assert_eq!(single_read, 0);
     assert_eq!(multiple_read, &[0, 1]);
+
+    // The whole range is checked before anything is read
+    assert!(matches!(
+        ram.read_many(1000, 100),
+        Err(OutOfBoundsError(1024))
+    ));
+    assert!(matches!(
+        ram.read_many(1, usize::MAX),
+        Err(OutOfBoundsError(1024))
+    ));
+    assert!(matches!(
+        ram.read_many(2000, 0),
+        Err(OutOfBoundsError(2000))
+    ));
 }
 
 #[test]
@@ -69,3 +84,647 @@ fn rom_read() {
     assert_eq!(single_read, 1);
     assert_eq!(multiple_read, &[2, 3, 4]);
 }
+
+#[test]
+fn bytecode_roundtrip() {
+    use common::bytecode::Executable;
+
+    let executable = Executable::new(vec![0xdeadbeef, 1, 2]);
+    let bytes = executable.to_bytes();
+
+    assert_eq!(Executable::from_bytes(&bytes).unwrap(), executable);
+    assert_eq!(
+        Executable::from_legacy_hex(&executable.to_legacy_hex()).unwrap(),
+        executable
+    );
+}
+
+#[test]
+fn bytecode_rejects_corruption() {
+    use common::bytecode::{BytecodeError, Executable, Section, SectionKind};
+
+    let bytes = Executable::new(vec![1, 2, 3]).to_bytes();
+
+    let mut flipped = bytes.clone();
+    *flipped.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        Executable::from_bytes(&flipped),
+        Err(BytecodeError::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+        Executable::from_bytes(&bytes[..10]),
+        Err(BytecodeError::Truncated)
+    ));
+    assert!(matches!(
+        Executable::from_bytes(b"not bytecode at all!"),
+        Err(BytecodeError::BadMagic)
+    ));
+
+    // Code has to start at 0, data has to end inside the address space
+    let mut moved = Executable::new(vec![1]);
+    moved.sections[0].address = 0xffffffff;
+    assert!(matches!(
+        Executable::from_bytes(&moved.to_bytes()),
+        Err(BytecodeError::InvalidSectionAddress(0))
+    ));
+    let mut past_end = Executable::new(vec![1]);
+    past_end.sections.push(Section {
+        kind: SectionKind::Data,
+        address: 0xffffffff,
+        data: vec![1, 2],
+    });
+    assert!(matches!(
+        Executable::from_bytes(&past_end.to_bytes()),
+        Err(BytecodeError::InvalidSectionAddress(1))
+    ));
+}
+
+#[test]
+fn debugger_number_syntax() {
+    use crate::debugger::parse_number;
+
+    for (value, expected) in [
+        ("10", 10),
+        ("0x1f", 31),
+        ("#1f", 31),
+        ("0b101", 5),
+        ("$101", 5),
+        ("-1", u32::MAX),
+        ("4294967295", u32::MAX),
+    ] {
+        assert_eq!(parse_number(value).unwrap(), expected, "{}", value);
+    }
+    for value in ["0xzz", "12a", "", "4294967296", "0x", "--1"] {
+        assert!(parse_number(value).is_err(), "{}", value);
+    }
+}
+
+#[test]
+fn debugger_commands() {
+    use crate::debugger::{CommandError, Debugger};
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::Register;
+
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(1)).into(),
+        Opcode::Call(Bit13Literal(4)).into(),
+        Opcode::Imm(Register::B, Bit13Literal(2)).into(),
+        Opcode::Jmp(Bit13Literal(6)).into(),
+        // Function setting D
+        Opcode::Imm(Register::D, Bit13Literal(4)).into(),
+        Opcode::Ret.into(),
+        Opcode::Imm(Register::C, Bit13Literal(3)).into(),
+    ];
+
+    let mut machine = CrazyVM::new(&program, 64);
+    let mut debugger = Debugger::new(&mut machine, None);
+    debugger.execute(&["step"]).unwrap();
+    // next runs the whole call
+    debugger.execute(&["n"]).unwrap();
+    debugger.execute(&["b", "0x6"]).unwrap();
+    debugger.execute(&["continue"]).unwrap();
+    drop(debugger);
+    assert_eq!(machine.registers()[Register::PC], 6);
+    assert_eq!(machine.registers()[Register::B], 2);
+    assert_eq!(machine.registers()[Register::D], 4);
+    assert_eq!(machine.registers()[Register::C], 0);
+
+    let mut debugger = Debugger::new(&mut machine, None);
+    assert!(debugger.execute(&["delete", "6"]).is_err());
+    debugger.execute(&["set", "E", "0b11"]).unwrap();
+    debugger.execute(&["set", "F", "1.5"]).unwrap();
+    // Huge counts are clamped to the memory size instead of aborting
+    debugger.execute(&["mem", "0", "4000000000"]).unwrap();
+    assert!(debugger.execute(&["mem", "60", "4000000000"]).is_err());
+    debugger.execute(&["list", "4294967295"]).unwrap();
+    assert!(debugger.execute(&["frobnicate"]).is_err());
+    assert!(debugger.execute(&["step", "12x"]).is_err());
+    // Runs off the end of the program
+    debugger.execute(&["s", "5"]).unwrap();
+    assert!(debugger.execute(&["s"]).is_err());
+    assert!(matches!(debugger.execute(&["q"]), Err(CommandError::Quit)));
+    drop(debugger);
+    assert_eq!(machine.registers()[Register::C], 3);
+    assert_eq!(machine.registers()[Register::E], 3);
+    assert_eq!(machine.registers()[Register::F], 1.5f32.to_bits());
+
+    // Breakpoints stop next inside the called function
+    let mut machine = CrazyVM::new(&program, 64);
+    let mut debugger = Debugger::new(&mut machine, None);
+    debugger.execute(&["b", "5"]).unwrap();
+    debugger.execute(&["s"]).unwrap();
+    debugger.execute(&["next"]).unwrap();
+    drop(debugger);
+    assert_eq!(machine.registers()[Register::PC], 5);
+}
+
+#[test]
+fn syscall_captured_output() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::Register;
+    use common::syscall::StdSyscalls;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let program: Vec<u32> = vec![
+        Opcode::PushImm(Bit13Literal(104)).into(),
+        Opcode::PushImm(Bit13Literal(105)).into(),
+        Opcode::Imm(Register::A, Bit13Literal(2)).into(),
+        Opcode::Imm(Register::C, Bit13Literal(0)).into(),
+        Opcode::Imm(Register::D, Bit13Literal(2)).into(),
+        Opcode::Syscall.into(),
+        Opcode::Imm(Register::A, Bit13Literal(0)).into(),
+        Opcode::Imm(Register::B, Bit13Literal(3)).into(),
+        Opcode::Syscall.into(),
+    ];
+    let output = Shared::default();
+    let mut machine = CrazyVM::new(&program, 64);
+    machine.set_syscall_handler(Box::new(StdSyscalls::new(std::io::empty(), output.clone())));
+
+    let exit_code = loop {
+        if let Some(code) = machine.step().unwrap() {
+            break code;
+        }
+    };
+
+    assert_eq!(exit_code, 3);
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"hi");
+}
+
+#[test]
+fn oversized_write_syscall() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::{CrazyVM, RuntimeError};
+    use common::registers::Register;
+    use common::syscall::{StdSyscalls, SyscallError};
+
+    // sys_write with a length of 8191 * 8191 * 32 words
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::D, Bit13Literal(8191)).into(),
+        Opcode::Mul(Register::D, Register::D, Register::D).into(),
+        Opcode::Imm(Register::C, Bit13Literal(32)).into(),
+        Opcode::Mul(Register::D, Register::C, Register::D).into(),
+        Opcode::Imm(Register::C, Bit13Literal(0)).into(),
+        Opcode::Imm(Register::A, Bit13Literal(2)).into(),
+        Opcode::Syscall.into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+    machine.set_syscall_handler(Box::new(StdSyscalls::new(
+        std::io::empty(),
+        std::io::sink(),
+    )));
+
+    for _ in 0..6 {
+        machine.step().unwrap();
+    }
+    assert!(matches!(
+        machine.step(),
+        Err(RuntimeError::Syscall(6, SyscallError::OutOfBounds(_)))
+    ));
+}
+
+#[test]
+fn unknown_syscall() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::{CrazyVM, RuntimeError};
+    use common::registers::Register;
+    use common::syscall::SyscallError;
+
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(42)).into(),
+        Opcode::Syscall.into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    machine.step().unwrap();
+    assert!(matches!(
+        machine.step(),
+        Err(RuntimeError::Syscall(1, SyscallError::Unknown(42)))
+    ));
+}
+
+#[test]
+fn load_store() {
+    use common::data_structures::error::OutOfBoundsError;
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::{CrazyVM, RuntimeError};
+    use common::registers::Register;
+
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(1234)).into(),
+        Opcode::Imm(Register::B, Bit13Literal(10)).into(),
+        Opcode::Store(Register::A, Register::B, Bit13Literal(5)).into(),
+        Opcode::Load(Register::C, Register::B, Bit13Literal(5)).into(),
+        Opcode::Load(Register::D, Register::B, Bit13Literal(100)).into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    for _ in 0..4 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.memory().read(15).unwrap(), 1234);
+    assert_eq!(machine.registers()[Register::C], 1234);
+    assert!(matches!(
+        machine.step(),
+        Err(RuntimeError::OutOfBounds(4, OutOfBoundsError(110)))
+    ));
+}
+
+#[test]
+fn bitwise() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::Register;
+
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(0b1100)).into(),
+        Opcode::Imm(Register::B, Bit13Literal(0b1010)).into(),
+        Opcode::Xor(Register::A, Register::B, Register::C).into(),
+        Opcode::Not(Register::Zero, Register::D).into(),
+        Opcode::Imm(Register::B, Bit13Literal(4)).into(),
+        Opcode::Sar(Register::D, Register::B, Register::A).into(),
+        Opcode::Shr(Register::D, Register::B, Register::B).into(),
+        Opcode::Push(Register::C).into(),
+        Opcode::PushImm(Bit13Literal(2)).into(),
+        Opcode::StackShl.into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+    for _ in 0..program.len() {
+        machine.step().unwrap();
+    }
+
+    assert_eq!(machine.registers()[Register::C], 0b0110);
+    assert_eq!(machine.registers()[Register::D], u32::MAX);
+    assert_eq!(machine.registers()[Register::A], u32::MAX);
+    assert_eq!(machine.registers()[Register::B], u32::MAX >> 4);
+    assert_eq!(machine.memory().read(0).unwrap(), 0b11000);
+}
+
+#[test]
+fn wide_immediates() {
+    use common::instructions::{Bit16Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::Register;
+
+    let program: Vec<u32> = vec![
+        Opcode::ImmHi(Register::A, Bit16Literal(0x1234)).into(),
+        Opcode::ImmLo(Register::B, Bit16Literal(0xBEEF)).into(),
+        Opcode::ImmHi(Register::B, Bit16Literal(0xDEAD)).into(),
+        Opcode::ImmLo(Register::B, Bit16Literal(0xFFFF)).into(),
+        Opcode::ImmHi(Register::B, Bit16Literal(0x0001)).into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    machine.step().unwrap();
+    assert_eq!(machine.registers()[Register::A], 0x12340000);
+    machine.step().unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.registers()[Register::B], 0xDEADBEEF);
+    machine.step().unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.registers()[Register::B], 0x0001FFFF);
+}
+
+#[test]
+fn arithmetic_faults() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::{CrazyVM, RuntimeError};
+    use common::registers::Register;
+
+    let run = |program: &[u32]| {
+        let mut machine = CrazyVM::new(program, 64);
+        loop {
+            if let Err(e) = machine.step() {
+                return e;
+            }
+        }
+    };
+
+    let div_zero: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(1)).into(),
+        Opcode::Div(Register::A, Register::Zero, Register::B).into(),
+    ];
+    assert!(matches!(run(&div_zero), RuntimeError::DivisionByZero(1)));
+
+    let empty_stack: Vec<u32> = vec![
+        Opcode::PushImm(Bit13Literal(1)).into(),
+        Opcode::StackAdd.into(),
+    ];
+    assert!(matches!(run(&empty_stack), RuntimeError::StackUnderflow(1)));
+    assert_eq!(RuntimeError::StackUnderflow(1).pc(), Some(1));
+
+    let bad_ret: Vec<u32> = vec![Opcode::Ret.into()];
+    assert!(matches!(run(&bad_ret), RuntimeError::StackUnderflow(0)));
+}
+
+#[test]
+fn invalid_instruction() {
+    use common::instructions::{DecodeError, DecodeField, Opcode};
+    use common::machine::{CrazyVM, RuntimeError};
+
+    let word = 0xdead00ff;
+    assert_eq!(
+        Opcode::try_from(word).unwrap_err(),
+        DecodeError {
+            word,
+            field: DecodeField::Opcode
+        }
+    );
+
+    let mut machine = CrazyVM::new(&[word], 64);
+    assert!(matches!(
+        machine.step(),
+        Err(RuntimeError::InvalidInstruction(
+            0,
+            DecodeError {
+                word: 0xdead00ff,
+                ..
+            }
+        ))
+    ));
+
+    // Register 15 doesn't exist
+    let word = u32::from(Opcode::Push(common::registers::Register::H)) | 0xf00;
+    assert_eq!(
+        Opcode::try_from(word).unwrap_err(),
+        DecodeError {
+            word,
+            field: DecodeField::R1
+        }
+    );
+}
+
+#[test]
+fn arithmetic_flags() {
+    use common::instructions::{Bit13Literal, Bit16Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::{flags, Register};
+
+    let program: Vec<u32> = vec![
+        // A = 0x7fffffff, B = 1
+        Opcode::ImmLo(Register::A, Bit16Literal(0xffff)).into(),
+        Opcode::ImmHi(Register::A, Bit16Literal(0x7fff)).into(),
+        Opcode::Imm(Register::B, Bit13Literal(1)).into(),
+        // Signed overflow into the sign bit
+        Opcode::Add(Register::A, Register::B, Register::C).into(),
+        // Borrow, wraps to u32::MAX
+        Opcode::Sub(Register::Zero, Register::B, Register::C).into(),
+        // Carry out of bit 31, result 0
+        Opcode::Add(Register::C, Register::B, Register::C).into(),
+        Opcode::Jc(Bit13Literal(8)).into(),
+        Opcode::Imm(Register::D, Bit13Literal(1)).into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+    let step_flags = |machine: &mut CrazyVM| {
+        machine.step().unwrap();
+        machine.registers()[Register::Flag] & flags::ARITHMETIC
+    };
+
+    for _ in 0..3 {
+        machine.step().unwrap();
+    }
+    assert_eq!(step_flags(&mut machine), flags::OVERFLOW | flags::SIGN);
+    assert_eq!(step_flags(&mut machine), flags::CARRY | flags::SIGN);
+    assert_eq!(machine.registers()[Register::C], u32::MAX);
+    assert_eq!(step_flags(&mut machine), flags::CARRY | flags::ZERO);
+    machine.step().unwrap();
+    assert_eq!(machine.registers()[Register::PC], 8);
+}
+
+#[test]
+fn signed_arithmetic() {
+    use common::instructions::{Bit13Literal, Bit16Literal, Opcode};
+    use common::machine::{CrazyVM, RuntimeError};
+    use common::registers::{flags, Register};
+
+    let program: Vec<u32> = vec![
+        // A = -7, B = 2
+        Opcode::SImm(Register::A, Bit13Literal::from_signed(-7).unwrap()).into(),
+        Opcode::Imm(Register::B, Bit13Literal(2)).into(),
+        Opcode::IDiv(Register::A, Register::B, Register::C).into(),
+        Opcode::IMul(Register::A, Register::B, Register::D).into(),
+        Opcode::SCmp(Register::A, Register::B).into(),
+        Opcode::Cmp(Register::A, Register::B).into(),
+        // i32::MIN / -1
+        Opcode::ImmLo(Register::A, Bit16Literal(0)).into(),
+        Opcode::ImmHi(Register::A, Bit16Literal(0x8000)).into(),
+        Opcode::SImm(Register::B, Bit13Literal::from_signed(-1).unwrap()).into(),
+        Opcode::IDiv(Register::A, Register::B, Register::C).into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    for _ in 0..4 {
+        machine.step().unwrap();
+    }
+    // Division truncates towards zero
+    assert_eq!(machine.registers()[Register::C] as i32, -3);
+    assert_eq!(machine.registers()[Register::D] as i32, -14);
+
+    machine.step().unwrap();
+    assert_ne!(machine.registers()[Register::Flag] & flags::LESS, 0);
+    machine.step().unwrap();
+    assert_ne!(machine.registers()[Register::Flag] & flags::GREATER, 0);
+
+    for _ in 0..3 {
+        machine.step().unwrap();
+    }
+    assert!(matches!(
+        machine.step(),
+        Err(RuntimeError::ArithmeticOverflow(9))
+    ));
+}
+
+#[test]
+fn call_frames() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::Register;
+
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(5)).into(),
+        Opcode::Call(Bit13Literal(3)).into(),
+        Opcode::Jmp(Bit13Literal(2)).into(),
+        // factorial: A = A!
+        Opcode::Cmp(Register::A, Register::A).into(),
+        Opcode::Jz(Bit13Literal(12)).into(),
+        Opcode::Push(Register::A).into(),
+        Opcode::Imm(Register::B, Bit13Literal(1)).into(),
+        Opcode::Sub(Register::A, Register::B, Register::A).into(),
+        Opcode::Call(Bit13Literal(3)).into(),
+        Opcode::Pop(Register::B).into(),
+        Opcode::Mul(Register::A, Register::B, Register::A).into(),
+        Opcode::Ret.into(),
+        // Early return, leaving a value on the stack
+        Opcode::Push(Register::A).into(),
+        Opcode::Imm(Register::A, Bit13Literal(1)).into(),
+        Opcode::Ret.into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    machine.step().unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.registers()[Register::SP], 2);
+    assert_eq!(machine.registers()[Register::FP], 2);
+
+    while machine.registers()[Register::PC] != 2 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.registers()[Register::A], 120);
+    assert_eq!(machine.registers()[Register::SP], 0);
+    assert_eq!(machine.registers()[Register::FP], 0);
+}
+
+#[test]
+fn floating_point() {
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::CrazyVM;
+    use common::registers::{flags, Register};
+
+    let program: Vec<u32> = vec![
+        Opcode::Imm(Register::A, Bit13Literal(3)).into(),
+        Opcode::Imm(Register::B, Bit13Literal(4)).into(),
+        Opcode::ItoF(Register::A, Register::A).into(),
+        Opcode::ItoF(Register::B, Register::B).into(),
+        // 0.75
+        Opcode::FDiv(Register::A, Register::B, Register::C).into(),
+        Opcode::FCmp(Register::C, Register::A).into(),
+        // 0.0 / 0.0 = NaN, unordered
+        Opcode::FDiv(Register::Zero, Register::Zero, Register::D).into(),
+        Opcode::FCmp(Register::D, Register::D).into(),
+        // -0.75 truncates to 0, -3.0 to -3
+        Opcode::FSub(Register::Zero, Register::C, Register::C).into(),
+        Opcode::FtoI(Register::C, Register::E).into(),
+        Opcode::FSub(Register::Zero, Register::A, Register::A).into(),
+        Opcode::FtoI(Register::A, Register::F).into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    for _ in 0..5 {
+        machine.step().unwrap();
+    }
+    assert_eq!(f32::from_bits(machine.registers()[Register::C]), 0.75);
+    machine.step().unwrap();
+    assert_eq!(
+        machine.registers()[Register::Flag],
+        flags::LESS | flags::NOT_EQUAL
+    );
+
+    machine.step().unwrap();
+    assert!(f32::from_bits(machine.registers()[Register::D]).is_nan());
+    machine.step().unwrap();
+    assert_eq!(machine.registers()[Register::Flag], flags::NOT_EQUAL);
+
+    for _ in 0..4 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.registers()[Register::E], 0);
+    assert_eq!(machine.registers()[Register::F] as i32, -3);
+}
+
+#[test]
+fn data_section_loading() {
+    use common::bytecode::{Executable, Section, SectionKind};
+    use common::machine::CrazyVM;
+    use common::registers::Register;
+
+    let mut executable = Executable::new(vec![]);
+    executable.sections.push(Section {
+        kind: SectionKind::Data,
+        address: 4,
+        data: vec![7, 8, 9],
+    });
+
+    let machine = CrazyVM::from_executable(&executable, 64).unwrap();
+    assert_eq!(machine.memory().read_many(4, 3).unwrap(), [7, 8, 9]);
+    // The stack starts after the data
+    assert_eq!(machine.registers()[Register::SP], 7);
+
+    assert!(CrazyVM::from_executable(&executable, 5).is_err());
+
+    // A section ending past the address space doesn't wrap around
+    executable.sections[1].address = u32::MAX;
+    assert!(CrazyVM::from_executable(&executable, 64).is_err());
+}
+
+#[test]
+fn call_stack_backtrace() {
+    use crate::utils::backtrace;
+    use common::debug_info::{DebugInfo, DebugSymbol};
+    use common::instructions::{Bit13Literal, Opcode};
+    use common::machine::{CrazyVM, Frame, RuntimeError};
+    use common::registers::Register;
+
+    let program: Vec<u32> = vec![
+        Opcode::Call(Bit13Literal(2)).into(),
+        Opcode::Jmp(Bit13Literal(1)).into(),
+        // outer: calls inner, then returns
+        Opcode::Call(Bit13Literal(4)).into(),
+        Opcode::Ret.into(),
+        // inner: pops until the stack underflows
+        Opcode::Pop(Register::A).into(),
+        Opcode::Jmp(Bit13Literal(4)).into(),
+    ];
+    let mut machine = CrazyVM::new(&program, 64);
+
+    machine.step().unwrap();
+    machine.step().unwrap();
+    assert_eq!(
+        machine.call_stack(),
+        [
+            Frame {
+                call_site: 0,
+                function: 2
+            },
+            Frame {
+                call_site: 2,
+                function: 4
+            },
+        ]
+    );
+
+    // Popping the return addresses doesn't lose track of the calls
+    let error = loop {
+        if let Err(e) = machine.step() {
+            break e;
+        }
+    };
+    assert!(matches!(error, RuntimeError::StackUnderflow(4)));
+    assert_eq!(machine.call_stack().len(), 2);
+
+    let expected = "\
+Backtrace, most recent call first:
+  #0 at 4, in 4
+  #1 at 2, in 2
+  #2 at 0";
+    assert_eq!(backtrace(&machine, 4, None), expected);
+
+    let symbol = |name: &str, address| DebugSymbol {
+        name: name.to_string(),
+        address,
+    };
+    let info = DebugInfo {
+        symbols: vec![symbol("main", 0), symbol("outer", 2), symbol("inner", 4)],
+        ..Default::default()
+    };
+    let expected = "\
+Backtrace, most recent call first:
+  #0 at inner, in inner
+  #1 at outer, in outer
+  #2 at main";
+    assert_eq!(backtrace(&machine, 4, Some(&info)), expected);
+}
@@ -1,37 +1,79 @@
-use std::io::Read;
+use common::bytecode::Executable;
+use common::debug_info::{sidecar_path, DebugInfo};
+use common::machine::CrazyVM;
 
-pub fn read_binary(name: &str) -> Option<Vec<u32>> {
-    let mut file = match std::fs::File::open(name) {
-        Ok(file) => file,
+/// Reads a bytecode file, `legacy_hex` selects the old hex text format
+pub fn read_binary(name: &str, legacy_hex: bool) -> Option<Executable> {
+    let content = match std::fs::read(name) {
+        Ok(content) => content,
         Err(e) => {
-            eprintln!("Failed to open file {}: {}", name, e);
+            eprintln!("Failed to read {}: {}", name, e);
             return None;
         }
     };
-    let mut content = String::new();
-    match file.read_to_string(&mut content) {
-        Ok(_) => {}
+
+    let executable = if legacy_hex {
+        match std::str::from_utf8(&content) {
+            Ok(text) => Executable::from_legacy_hex(text),
+            Err(e) => {
+                eprintln!("{} is not a hex text file: {}", name, e);
+                return None;
+            }
+        }
+    } else {
+        Executable::from_bytes(&content)
+    };
+
+    match executable {
+        Ok(executable) => Some(executable),
         Err(e) => {
-            eprintln!("Failed to read {}: {}", name, e);
-            return None;
+            eprintln!("Invalid bytecode file {}: {}", name, e);
+            None
         }
     }
-    let mut buf = vec![];
-
-    for byte in content.split_whitespace() {
-        let mut reversed = String::with_capacity(byte.len());
+}
 
-        for ch in byte.chars().rev() {
-            reversed.push(ch);
+/// Reads the debug info written next to a bytecode file, if there is any
+pub fn read_debug_info(name: &str) -> Option<DebugInfo> {
+    let path = sidecar_path(name);
+    let content = std::fs::read(&path).ok()?;
+    match DebugInfo::from_bytes(&content) {
+        Ok(info) => Some(info),
+        Err(e) => {
+            eprintln!("Ignoring invalid debug info {}: {}", path, e);
+            None
         }
+    }
+}
 
-        match u32::from_str_radix(&reversed, 16) {
-            Ok(num) => buf.push(num),
-            Err(e) => {
-                eprintln!("Invalid hex value in program: {}", e);
-                return None;
-            }
-        };
+/// The chain of calls that led to `pc`, innermost first. Every frame is the instruction
+/// being executed in a function, followed by the function it is in
+pub fn backtrace(machine: &CrazyVM, pc: u32, debug_info: Option<&DebugInfo>) -> String {
+    let describe = |address: u32| match debug_info {
+        Some(info) => info.locate(address).to_string(),
+        None => address.to_string(),
+    };
+    let function_name = |address: u32| match debug_info.and_then(|info| info.symbol(address)) {
+        Some((name, 0)) => name.to_string(),
+        _ => address.to_string(),
+    };
+
+    let frames = machine.call_stack();
+    // Each call site is in the function called by the frame before it
+    let addresses = frames.iter().rev().map(|frame| frame.call_site);
+    let functions = frames.iter().rev().map(|frame| Some(frame.function));
+
+    let mut out = String::from("Backtrace, most recent call first:");
+    for (i, (address, function)) in std::iter::once(pc)
+        .chain(addresses)
+        .zip(functions.chain(std::iter::once(None)))
+        .enumerate()
+    {
+        out.push_str(&format!("\n  #{} at {}", i, describe(address)));
+        if let Some(function) = function {
+            out.push_str(&format!(", in {}", function_name(function)));
+        }
     }
-    Some(buf)
+
+    out
 }